@@ -1,11 +1,17 @@
+use crate::devices::{
+    Parity, BAUD_RATES, DRIVERS, DRIVER_CHAFON, DRIVER_SERIAL, DRIVER_SIMULATOR, PRIMARY_BLOCKS,
+    SERIAL_READER_MODEL,
+};
+use crate::routes::access::{Network, ANY_ORIGIN};
 use log::*;
 use rocket::serde::{json, Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::Write;
 use std::path::Path;
 use std::{fs::File, io::Read, net::Ipv4Addr, net::Ipv6Addr, str::FromStr};
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(crate = "rocket::serde")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
 pub struct Config {
     address: String,
     port: u16,
@@ -13,6 +19,89 @@ pub struct Config {
     log_to_file: bool,
     max_log_size: u16,
     ask_when_writing: bool,
+    verify_after_writing: bool,
+    raw_blocks_access: bool,
+    events_poll_interval: u64,  // ms
+    device_check_interval: u64, // ms
+    // Friendly names of devices by their ids
+    device_names: HashMap<String, String>,
+    // Drivers which are loaded at startup
+    devices: Vec<DeviceConfig>,
+    // Websites which may use the server, e.g. https://library.example.org. "*" allows any
+    allowed_origins: Vec<String>,
+    // Token required from every client. Empty if it isn't required
+    api_token: String,
+    // Tokens of websites, which are used instead of api_token. The websites are allowed
+    origin_tokens: HashMap<String, String>,
+    // Ask the user whether a website, which isn't in the config, may use the server
+    pair_origins: bool,
+    // Where decisions of the user are kept
+    permissions_file: String,
+    // Remote clients which may use the server besides the computer itself
+    allowed_networks: Vec<Network>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct DeviceConfig {
+    pub driver: String, // One of DRIVERS
+    pub enabled: bool,
+    // Title of the device(s) of the driver. The id is used if it's not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub read_only: bool,
+    // Number of memory blocks read from a tag
+    pub read_blocks: u8,
+    // Whether the reader signals reading and writing tags
+    pub led: bool,
+    pub buzzer: bool,
+    // Required by the serial driver
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<SerialPortConfig>,
+    // Path of a scenario file of the simulator. It has one built-in tag without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<String>,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            driver: String::new(),
+            enabled: true,
+            name: None,
+            read_only: false,
+            read_blocks: 28,
+            led: true,
+            buzzer: false,
+            serial: None,
+            scenario: None,
+        }
+    }
+}
+
+// Reader connected to a serial port, see devices::serial_reader
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SerialPortConfig {
+    pub port: String, // e.g. /dev/ttyUSB0
+    pub baud_rate: u32,
+    pub parity: Parity,
+    // Bus address of the reader, 255 is accepted by any reader
+    pub address: u8,
+    // Shown in the device id
+    pub model: String,
+}
+
+impl Default for SerialPortConfig {
+    fn default() -> Self {
+        Self {
+            port: String::new(),
+            baud_rate: 38400,
+            parity: Parity::Even,
+            address: 0xFF,
+            model: SERIAL_READER_MODEL.to_string(),
+        }
+    }
 }
 
 impl Default for Config {
@@ -24,6 +113,21 @@ impl Default for Config {
             log_level: "Info".to_string(),
             max_log_size: 5,
             ask_when_writing: false,
+            verify_after_writing: false,
+            raw_blocks_access: false,
+            events_poll_interval: 500,
+            device_check_interval: 2000,
+            device_names: HashMap::new(),
+            devices: vec![DeviceConfig {
+                driver: DRIVER_CHAFON.to_string(),
+                ..Default::default()
+            }],
+            allowed_origins: vec![ANY_ORIGIN.to_string()],
+            api_token: String::new(),
+            origin_tokens: HashMap::new(),
+            pair_origins: false,
+            permissions_file: "permissions.json".to_string(),
+            allowed_networks: Vec::new(),
         }
     }
 }
@@ -77,12 +181,13 @@ impl Config {
         let config_json: Result<Config, json::serde_json::Error> =
             json::from_str(contents.as_str());
 
-        if config_json.is_err() {
-            println!("JSON error: {:?}", config_json.unwrap_err().to_string());
-            return Err(());
-        }
-
-        let mut config = config_json.unwrap();
+        let mut config = match config_json {
+            Ok(c) => c,
+            Err(e) => {
+                println!("JSON error: {:?}", e.to_string());
+                return Err(());
+            }
+        };
 
         if Ipv4Addr::from_str(&config.address).is_err()
             && Ipv6Addr::from_str(&config.address).is_err()
@@ -91,6 +196,23 @@ impl Config {
             return Err(());
         }
 
+        if let Err(e) = config.check_devices() {
+            println!("Field 'devices' in config.json is incorrect: {e}");
+            return Err(());
+        }
+
+        for origin in config
+            .allowed_origins
+            .iter()
+            .chain(config.origin_tokens.keys())
+        {
+            let is_url = origin.starts_with("http://") || origin.starts_with("https://");
+            if origin != ANY_ORIGIN && (!is_url || origin.ends_with('/')) {
+                println!("Origin {origin} in config.json is incorrect. It must look like https://library.example.org");
+                return Err(());
+            }
+        }
+
         match config.log_level.as_str() {
             "Off" | "Error" | "Warn" | "Info" | "Debug" | "Trace" => (),
             _ => {
@@ -149,4 +271,151 @@ impl Config {
     pub fn ask_when_writing(&self) -> bool {
         self.ask_when_writing
     }
+
+    #[allow(unused)]
+    pub fn verify_after_writing(&self) -> bool {
+        self.verify_after_writing
+    }
+
+    #[allow(unused)]
+    pub fn raw_blocks_access(&self) -> bool {
+        self.raw_blocks_access
+    }
+
+    #[allow(unused)]
+    pub fn events_poll_interval(&self) -> u64 {
+        self.events_poll_interval
+    }
+
+    #[allow(unused)]
+    pub fn device_check_interval(&self) -> u64 {
+        self.device_check_interval
+    }
+
+    #[allow(unused)]
+    pub fn device_name(&self, id: &str) -> Option<&String> {
+        self.device_names.get(id)
+    }
+
+    #[allow(unused)]
+    pub fn devices(&self) -> &Vec<DeviceConfig> {
+        &self.devices
+    }
+
+    #[allow(unused)]
+    pub fn allowed_origins(&self) -> &Vec<String> {
+        &self.allowed_origins
+    }
+
+    #[allow(unused)]
+    pub fn api_token(&self) -> Option<&String> {
+        Some(&self.api_token).filter(|t| !t.is_empty())
+    }
+
+    #[allow(unused)]
+    pub fn origin_token(&self, origin: &str) -> Option<&String> {
+        self.origin_tokens.get(origin)
+    }
+
+    #[allow(unused)]
+    pub fn pair_origins(&self) -> bool {
+        self.pair_origins
+    }
+
+    #[allow(unused)]
+    pub fn permissions_file(&self) -> &str {
+        &self.permissions_file
+    }
+
+    #[allow(unused)]
+    pub fn allowed_networks(&self) -> &Vec<Network> {
+        &self.allowed_networks
+    }
+
+    fn check_devices(&self) -> Result<(), String> {
+        let mut ports = vec![];
+        let mut scenarios = vec![];
+        let mut has_chafon = false;
+        for (i, device) in self.devices.iter().enumerate() {
+            if !DRIVERS.contains(&device.driver.as_str()) {
+                return Err(format!(
+                    "device {i} has unknown driver '{}'. Options are: {}",
+                    device.driver,
+                    DRIVERS.join(", ")
+                ));
+            }
+            if device.read_blocks < PRIMARY_BLOCKS {
+                return Err(format!(
+                    "device {i} must read at least {PRIMARY_BLOCKS} blocks"
+                ));
+            }
+            if !device.enabled {
+                continue;
+            }
+            match (device.driver.as_str(), &device.serial) {
+                (DRIVER_CHAFON, _) if has_chafon => {
+                    return Err(format!("device {i}: driver 'chafon' is already loaded"));
+                }
+                (DRIVER_CHAFON, _) => has_chafon = true,
+                (DRIVER_SERIAL, None) => {
+                    return Err(format!("device {i} has no 'serial' settings"));
+                }
+                (DRIVER_SERIAL, Some(serial)) => {
+                    if serial.port.is_empty() || ports.contains(&&serial.port) {
+                        return Err(format!("device {i} has an empty or repeated port"));
+                    }
+                    if !BAUD_RATES.contains(&serial.baud_rate) {
+                        return Err(format!(
+                            "device {i} has incorrect baud rate. Options are: {BAUD_RATES:?}"
+                        ));
+                    }
+                    ports.push(&serial.port);
+                }
+                (DRIVER_SIMULATOR, _) => {
+                    if scenarios.contains(&&device.scenario) {
+                        return Err(format!("device {i} has a repeated scenario"));
+                    }
+                    scenarios.push(&device.scenario);
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn devices_section() {
+    let config: Config = json::from_str(
+        r#"{"devices": [
+            {"driver": "chafon", "led": false},
+            {"driver": "serial", "name": "Reader", "read_only": true, "serial": {"port": "/dev/ttyUSB0"}},
+            {"driver": "serial", "enabled": false},
+            {"driver": "simulator"},
+            {"driver": "simulator", "scenario": "tags.json"}
+        ]}"#,
+    )
+    .unwrap();
+    assert!(config.check_devices().is_ok());
+    assert!(!config.devices[0].led);
+    assert_eq!(config.devices[0].read_blocks, 28);
+    let serial = config.devices[1].serial.as_ref().unwrap();
+    assert_eq!((serial.baud_rate, serial.parity), (38400, Parity::Even));
+
+    // Old configs without the section load the USB reader
+    let config: Config = json::from_str("{}").unwrap();
+    assert_eq!(config.devices[0].driver, DRIVER_CHAFON);
+
+    for devices in [
+        r#"[{"driver": "unknown"}]"#,
+        r#"[{"driver": "serial"}]"#,
+        r#"[{"driver": "serial", "serial": {"port": "COM1", "baud_rate": 1200}}]"#,
+        r#"[{"driver": "serial", "serial": {"port": "COM1"}}, {"driver": "serial", "serial": {"port": "COM1"}}]"#,
+        r#"[{"driver": "chafon"}, {"driver": "chafon"}]"#,
+        r#"[{"driver": "chafon", "read_blocks": 2}]"#,
+        r#"[{"driver": "simulator"}, {"driver": "simulator"}]"#,
+    ] {
+        let config: Config = json::from_str(&format!(r#"{{"devices": {devices}}}"#)).unwrap();
+        assert!(config.check_devices().is_err(), "{devices}");
+    }
 }
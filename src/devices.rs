@@ -1,46 +1,392 @@
 mod cf_rh320u_93;
+mod handle;
+mod read_only;
+mod serial_port;
+mod serial_reader;
+mod simulator;
 #[cfg(test)]
-mod test_device;
+pub mod test_device;
 
 use self::cf_rh320u_93::CfRh320u93;
-use crate::{rfid_items::DanishRfidItem, routes::write_tags::WriteResponse};
-use std::{collections::HashMap, sync::Mutex};
+pub use self::handle::{DeviceHandle, DeviceState};
+use self::read_only::ReadOnly;
+pub use self::serial_port::{Parity, BAUD_RATES};
+use self::serial_reader::SerialReader;
+pub use self::serial_reader::DEFAULT_MODEL as SERIAL_READER_MODEL;
+use self::simulator::Simulator;
+use crate::config::{Config, DeviceConfig};
+use crate::rfid_items::danish::PRIMARY_BLOCK_SIZE;
+use crate::routes::write_tags::{WriteError, WriteResponse};
+use log::*;
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
 
-type DeviceMutexBoxT = Mutex<Box<dyn Device>>;
+// Names of drivers in the `devices` section of the config
+pub const DRIVER_CHAFON: &str = "chafon";
+pub const DRIVER_SERIAL: &str = "serial";
+pub const DRIVER_SIMULATOR: &str = "simulator";
+pub const DRIVERS: [&str; 3] = [DRIVER_CHAFON, DRIVER_SERIAL, DRIVER_SIMULATOR];
+
+// Size of a memory block of ISO 15693 tags used in libraries
+pub const BLOCK_SIZE: usize = 4;
+// Number of blocks covering the primary block of the Danish data model
+pub const PRIMARY_BLOCKS: u8 = (PRIMARY_BLOCK_SIZE / BLOCK_SIZE) as u8;
+
+// ISO 15693 AFI value of a secured library item
+pub const AFI_SECURED: u8 = 0x07;
+// ISO 15693 AFI value of a checked out library item
+pub const AFI_CHECKED_OUT: u8 = 0xC2;
+
+// Tag as it's read from or written to a reader. The memory is decoded
+// by one of the data models in rfid_items
+#[derive(Debug, Clone)]
+pub struct RawTag {
+    pub uid: Vec<u8>,
+    pub dsfid: Option<u8>, // None if the reader is unable to get it
+    pub memory: Vec<u8>,
+}
+
+// Security state of a tag. `afi` is None if the reader is unable to read it
+#[derive(Debug)]
+pub struct TagSecurity {
+    pub tag_id: String,
+    pub afi: Option<u8>,
+}
+
+// Result of ISO 15693 Get System Information command.
+// Fields are None if a tag or a reader doesn't report them
+#[derive(Debug, Default)]
+pub struct TagInfo {
+    pub uid: Vec<u8>,
+    pub dsfid: Option<u8>,
+    pub afi: Option<u8>,
+    pub block_size: Option<u8>,
+    pub block_count: Option<u16>,
+    pub ic_reference: Option<u8>,
+}
+
+impl TagInfo {
+    // Manufacturer code follows 0xE0 byte of a UID (ISO/IEC 7816-6).
+    // Readers report UIDs either MSB or LSB first
+    pub fn manufacturer(&self) -> Option<&'static str> {
+        let code = match self.uid[..] {
+            [0xE0, code, ..] => code,
+            [.., code, 0xE0] if self.uid.len() == 8 => code,
+            _ => return None,
+        };
+        Some(match code {
+            0x01 => "Motorola",
+            0x02 => "STMicroelectronics",
+            0x03 => "Hitachi",
+            0x04 => "NXP Semiconductors",
+            0x05 => "Infineon Technologies",
+            0x07 => "Texas Instruments",
+            0x08 => "Fujitsu",
+            0x16 => "EM Microelectronic-Marin",
+            _ => return None,
+        })
+    }
+}
+
+// Settings of a driver from the config
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceOptions {
+    // Number of memory blocks read from a tag. Tags with a smaller memory
+    // are read up to the end of the primary block
+    pub read_blocks: u8,
+    pub led: bool,
+    pub buzzer: bool,
+}
+
+impl From<&DeviceConfig> for DeviceOptions {
+    fn from(config: &DeviceConfig) -> Self {
+        Self {
+            read_blocks: config.read_blocks,
+            led: config.led,
+            buzzer: config.buzzer,
+        }
+    }
+}
 
 pub trait Device: Send + Sync {
     fn connect(&mut self);
     fn is_connected(&self) -> bool;
+    // Description of a failure which isn't just an unplugged device
+    fn error(&self) -> Option<String> {
+        None
+    }
     fn multi_tag_is_supported(&self) -> bool;
     fn compound_data_is_supported(&self) -> bool;
     fn is_read_only(&self) -> bool;
-    fn get_items(&mut self) -> Vec<DanishRfidItem>; // List<RfidItem>
-    //fn set_tags_security(&self, params: HashMap<String, bool>) -> Result<(),()>;
-    fn write_tags(&mut self, items: Vec<DanishRfidItem>) -> Vec<WriteResponse>;
+    // Whether security bits (AFI and EAS) of tags can be changed
+    fn security_is_supported(&self) -> bool;
+    // Whether blocks of tags can be locked, see lock_blocks()
+    fn lock_is_supported(&self) -> bool;
+    // Whether system information of tags is known, see get_tag_info()
+    fn tag_info_is_supported(&self) -> bool;
+    // Whether the reader has a buzzer which can be used, see beep()
+    fn beep_is_supported(&self) -> bool;
+    fn get_tags(&mut self) -> Vec<RawTag>;
+    fn get_tags_security(&mut self) -> Vec<TagSecurity>;
+    // Returns None if there's no tag with this UID
+    fn get_tag_info(&mut self, uid: &[u8]) -> Option<TagInfo>;
+    // Keys are tag ids, values are whether a tag should be secured
+    fn set_tags_security(&mut self, params: HashMap<String, bool>) -> Vec<WriteResponse>;
+    // Returns a response for every tag in the order of `tags`. Its id is the UID
+    // of the written tag, which is known even if the tag was requested without it
+    fn write_tags(&mut self, tags: Vec<RawTag>) -> Vec<WriteResponse>;
+    // Raw access to tag memory for debugging and repairing tags
+    fn read_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<Vec<u8>, WriteError>;
+    fn write_blocks(&mut self, uid: &[u8], first_block: u8, data: &[u8]) -> Result<(), WriteError>;
+    // Permanently locks blocks of a tag, so they can't be written anymore
+    fn lock_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<(), WriteError>;
+    // Makes a short sound, so the user can tell which reader is which
+    fn beep(&mut self) -> Result<(), WriteError>;
 }
 
+// Devices are shared between request handlers and background pollers.
+// Units of USB readers which are plugged in later are added while the server is running
+#[derive(Clone)]
 pub struct DevicesList {
-    devices: HashMap<String, DeviceMutexBoxT>,
+    devices: Arc<RwLock<HashMap<String, Arc<DeviceHandle>>>>,
 }
 
 impl DevicesList {
-    pub fn new() -> Self {
-        let mut devices: HashMap<String, DeviceMutexBoxT> = HashMap::new();
+    pub fn new(config: &Config) -> Self {
+        Self::with_units(config, cf_rh320u_93::units)
+    }
 
-        #[cfg(test)]
-        devices.insert(
-            "Test Device".to_string(),
-            Mutex::new(Box::new(self::test_device::TestDevice {})),
-        );
-        devices.insert(
-            "Chafon CF-RH320U-93".to_string(),
-            Mutex::new(Box::new(CfRh320u93::new())),
-        );
+    fn with_units(config: &Config, units: cf_rh320u_93::Units) -> Self {
+        let list = Self {
+            devices: Arc::new(RwLock::new(HashMap::new())),
+        };
+
+        for device_config in config.devices().iter().filter(|d| d.enabled) {
+            let options = DeviceOptions::from(device_config);
+            let (id, device): (String, Box<dyn Device>) = match device_config.driver.as_str() {
+                DRIVER_CHAFON => {
+                    // Every attached unit has its own id
+                    list.add_units(config, device_config, units);
+                    list.watch_units(config, device_config, units);
+                    continue;
+                }
+                DRIVER_SERIAL => match &device_config.serial {
+                    Some(serial) => (
+                        format!("{}@{}", serial.model, serial.port),
+                        Box::new(SerialReader::new(serial, options)),
+                    ),
+                    None => continue,
+                },
+                DRIVER_SIMULATOR => {
+                    let scenario = device_config.scenario.clone();
+                    let id = match &scenario {
+                        Some(path) => format!("{}@{path}", simulator::MODEL),
+                        None => simulator::MODEL.to_string(),
+                    };
+                    (id, Box::new(Simulator::new(scenario, options)))
+                }
+                // Drivers are checked by Config::get()
+                driver => {
+                    error!("Unknown driver {driver}");
+                    continue;
+                }
+            };
+            list.insert(config, device_config, id, device);
+        }
+        list
+    }
+
+    // New units are looked for as often as devices are checked.
+    // The thread stops when the list is dropped
+    fn watch_units(
+        &self,
+        config: &Config,
+        device_config: &DeviceConfig,
+        units: cf_rh320u_93::Units,
+    ) {
+        let check_interval = Duration::from_millis(config.device_check_interval());
+        let devices = Arc::downgrade(&self.devices);
+        let (config, device_config) = (config.clone(), device_config.clone());
+        thread::spawn(move || loop {
+            thread::sleep(check_interval);
+            let Some(devices) = devices.upgrade() else {
+                break;
+            };
+            Self { devices }.add_units(&config, &device_config, units);
+        });
+    }
 
-        Self { devices }
+    // Adds units which aren't in the list yet
+    fn add_units(&self, config: &Config, device_config: &DeviceConfig, units: cf_rh320u_93::Units) {
+        for unit in units() {
+            let id = format!("{}@{}", cf_rh320u_93::MODEL, unit.id());
+            if self.devices.read().unwrap().contains_key(&id) {
+                continue;
+            }
+            info!("Device {id} is found");
+            let options = DeviceOptions::from(device_config);
+            let device = Box::new(CfRh320u93::new(unit.id(), units, options));
+            self.insert(config, device_config, id, device);
+        }
     }
 
-    pub fn get(&self) -> &HashMap<String, DeviceMutexBoxT> {
-        &self.devices
+    fn insert(
+        &self,
+        config: &Config,
+        device_config: &DeviceConfig,
+        id: String,
+        mut device: Box<dyn Device>,
+    ) {
+        let check_interval = Duration::from_millis(config.device_check_interval());
+        // Units of one driver get the same name, so their ids are added.
+        // It's done even for a single unit, so its title doesn't change when another is plugged in
+        let title = match (config.device_name(&id), &device_config.name) {
+            (Some(name), _) => name.clone(),
+            (None, Some(name)) if device_config.driver == DRIVER_CHAFON => format!("{name} ({id})"),
+            (None, Some(name)) => name.clone(),
+            (None, None) => id.clone(),
+        };
+        if device_config.read_only {
+            device = Box::new(ReadOnly(device));
+        }
+        let handle = DeviceHandle::spawn(&title, device, check_interval);
+        self.devices.write().unwrap().insert(id, Arc::new(handle));
+    }
+
+    // All devices by their ids
+    pub fn get(&self) -> HashMap<String, Arc<DeviceHandle>> {
+        self.devices.read().unwrap().clone()
+    }
+
+    pub fn device(&self, id: &str) -> Option<Arc<DeviceHandle>> {
+        self.devices.read().unwrap().get(id).cloned()
+    }
+
+    // Adds a device which isn't in the config, for tests of the API
+    #[cfg(test)]
+    pub fn add(&self, id: &str, device: Box<dyn Device>) {
+        let handle = DeviceHandle::spawn(id, device, Duration::from_secs(1));
+        self.devices
+            .write()
+            .unwrap()
+            .insert(id.to_string(), Arc::new(handle));
+    }
+}
+
+#[test]
+fn manufacturers() {
+    let info = |uid: [u8; 8]| TagInfo {
+        uid: uid.to_vec(),
+        ..Default::default()
+    };
+    // MSB first, as in tag ids of the API
+    let msb = info([0xE0, 0x04, 0x01, 0x50, 0x12, 0x34, 0x56, 0x78]);
+    assert_eq!(msb.manufacturer(), Some("NXP Semiconductors"));
+    // LSB first, as some readers report them
+    let lsb = info([0x78, 0x56, 0x34, 0x12, 0x50, 0x01, 0x07, 0xE0]);
+    assert_eq!(lsb.manufacturer(), Some("Texas Instruments"));
+
+    assert_eq!(info([0xE0, 0x7F, 0, 0, 0, 0, 0, 0]).manufacturer(), None);
+    assert_eq!(info([0x12, 0x04, 0, 0, 0, 0, 0, 0x34]).manufacturer(), None);
+}
+
+#[test]
+fn units_of_one_model() {
+    use rocket::serde::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    static IS_PLUGGED_IN: AtomicBool = AtomicBool::new(false);
+    fn units() -> Vec<cf_rh320u_93::UnitInfo> {
+        let unit = |path: &str, serial: Option<&str>| cf_rh320u_93::UnitInfo {
+            path: path.to_string(),
+            serial: serial.map(|s| s.to_string()),
+        };
+        let mut units = vec![unit("1-4.1", Some("A100")), unit("1-4.2", None)];
+        if IS_PLUGGED_IN.load(Ordering::Relaxed) {
+            units.push(unit("2-1", Some("B200")));
+        }
+        units
+    }
+
+    let config: Config = json::from_str(
+        r#"{
+            "log_to_file": false,
+            "device_check_interval": 10,
+            "devices": [{"driver": "chafon", "name": "Desk"}],
+            "device_names": {"Chafon CF-RH320U-93@A100": "Returns"}
+        }"#,
+    )
+    .unwrap();
+    let list = DevicesList::with_units(&config, units);
+    let titles = |list: &DevicesList| {
+        let mut titles: Vec<(String, String)> = list
+            .get()
+            .iter()
+            .map(|(id, device)| (id.clone(), device.title().to_string()))
+            .collect();
+        titles.sort();
+        titles
+    };
+    // Units are known by their serial numbers or by their ports if they have none
+    assert_eq!(
+        titles(&list),
+        [
+            (
+                "Chafon CF-RH320U-93@1-4.2",
+                "Desk (Chafon CF-RH320U-93@1-4.2)"
+            ),
+            ("Chafon CF-RH320U-93@A100", "Returns"),
+        ]
+        .map(|(id, title)| (id.to_string(), title.to_string()))
+    );
+
+    // A unit which is plugged in later is added
+    IS_PLUGGED_IN.store(true, Ordering::Relaxed);
+    let start = Instant::now();
+    while list.device("Chafon CF-RH320U-93@B200").is_none() {
+        assert!(start.elapsed() < Duration::from_secs(5), "Unit isn't added");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(list.get().len(), 3);
+
+    // Looking for units stops with the list
+    let devices = Arc::downgrade(&list.devices);
+    drop(list);
+    let start = Instant::now();
+    while devices.upgrade().is_some() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Units are watched"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn units_only_with_chafon() {
+    use rocket::serde::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn units() -> Vec<cf_rh320u_93::UnitInfo> {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        vec![]
     }
+    let config: Config = json::from_str(
+        r#"{
+            "log_to_file": false,
+            "device_check_interval": 10,
+            "devices": [{"driver": "simulator"}, {"driver": "chafon", "enabled": false}]
+        }"#,
+    )
+    .unwrap();
+    let _list = DevicesList::with_units(&config, units);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(CALLS.load(Ordering::Relaxed), 0);
 }
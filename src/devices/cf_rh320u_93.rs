@@ -1,30 +1,332 @@
-use super::Device;
+mod usb;
+
+use self::usb::Unit;
+pub use self::usb::{units, UnitInfo};
+use super::{
+    Device, DeviceOptions, RawTag, TagInfo, TagSecurity, AFI_CHECKED_OUT, AFI_SECURED,
+    PRIMARY_BLOCKS,
+};
 use crate::devices::WriteResponse;
-use crate::rfid_items::DanishRfidItem;
+use crate::rfid_items::{from_hex_string, to_hex_string};
 use crate::routes::write_tags::WriteError;
-use cf_rh320u_93_reader::*;
+use cf_rh320u_93_reader::{AccessFlag, ReaderError, StatusCode};
+use log::*;
+use std::{collections::HashMap, ops::Range};
+
+pub const MODEL: &str = "Chafon CF-RH320U-93";
+
+// Lists attached units. Tests use fake ones
+pub type Units = fn() -> Vec<UnitInfo>;
 
 pub struct CfRh320u93 {
-    handle: Result<CFRH320U93, ReaderError>,
+    handle: Result<Unit, ReaderError>,
+    // The unit which is used, see UnitInfo::id()
+    unit_id: String,
+    units: Units,
+    options: DeviceOptions,
 }
 
 impl CfRh320u93 {
-    pub fn new() -> Self {
+    // The unit is opened later on the thread of the device, see connect()
+    pub fn new(unit_id: &str, units: Units, options: DeviceOptions) -> Self {
         Self {
-            handle: CFRH320U93::open(),
+            handle: Err(ReaderError::UsbError(rusb::Error::NoDevice)),
+            unit_id: unit_id.to_string(),
+            units,
+            options,
+        }
+    }
+
+    fn open(&mut self) {
+        // The interface is released before it's claimed again
+        self.handle = Err(ReaderError::UsbError(rusb::Error::NoDevice));
+        if let Some(unit) = (self.units)().iter().find(|u| u.id() == self.unit_id) {
+            self.handle = Unit::open(&unit.path);
         }
     }
 }
 
-impl Device for CfRh320u93 {
-    fn connect(&mut self) {
-        // Reopen device if there was an error
-        if let Ok(device) = &self.handle {
-            if device.manufacturer().is_err() {
-                self.handle = CFRH320U93::open();
+// ISO 15693 commands of the reader which are used to address tags.
+// Sequences of them are tested against a virtual field of tags
+trait Transport {
+    fn iso15693_inventory(&self) -> Result<Vec<[u8; 8]>, ReaderError>;
+    fn iso15693_read(&self, flag: AccessFlag, skip: u8, blocks: u8)
+        -> Result<Vec<u8>, ReaderError>;
+    fn iso15693_write(&self, flag: AccessFlag, skip: u8, data: &[u8]) -> Result<(), ReaderError>;
+    fn iso15693_select(&self, flag: AccessFlag, uid: &[u8; 8]) -> Result<(), ReaderError>;
+    fn iso15693_stay_quiet(&self, flag: AccessFlag, uid: &[u8; 8]) -> Result<(), ReaderError>;
+    // Locked blocks can't be written anymore, even by the reader
+    fn iso15693_lock_block(&self, flag: AccessFlag, block: u8) -> Result<(), ReaderError>;
+    fn iso15693_write_afi(&self, flag: AccessFlag, afi: u8) -> Result<(), ReaderError>;
+    // Answer of a tag to ISO 15693 Get System Information, see system_info()
+    fn iso15693_get_system_info(&self, flag: AccessFlag) -> Result<Vec<u8>, ReaderError>;
+}
+
+// Reads the user memory to get optional blocks as well.
+// Tags with a smaller memory return an error, so only the primary block is read then
+fn read_user_memory(device: &impl Transport, blocks: u8) -> Vec<u8> {
+    device
+        .iso15693_read(AccessFlag::WithoutUID, 0, blocks)
+        .or_else(|_| device.iso15693_read(AccessFlag::WithoutUID, 0, PRIMARY_BLOCKS))
+        .unwrap_or_default()
+}
+
+// The driver ignores UIDs in Read and Write commands even with AccessFlag::WithUID,
+// so a tag is addressed by selecting it and making all other tags quiet.
+// Quiet tags don't answer non-addressed commands until they're selected again
+fn isolate(
+    device: &impl Transport,
+    inventory: &[[u8; 8]],
+    target: usize,
+) -> Result<(), ReaderError> {
+    device.iso15693_select(AccessFlag::WithUID, &inventory[target])?;
+    for (i, uid) in inventory.iter().enumerate() {
+        if i != target {
+            device.iso15693_stay_quiet(AccessFlag::WithUID, uid)?;
+        }
+    }
+    Ok(())
+}
+
+// Wakes up tags after isolate(). Selecting a tag moves the previously selected one
+// to the ready state, so all of them answer inventory again
+fn release(device: &impl Transport, inventory: &[[u8; 8]]) {
+    for uid in inventory {
+        if let Err(e) = device.iso15693_select(AccessFlag::WithUID, uid) {
+            warn!("Unable to wake up tag {}: {e}", to_hex_string(uid));
+        }
+    }
+}
+
+// Makes a tag the only one answering non-addressed commands.
+// Returns the inventory which has to be released afterwards
+fn address(device: &impl Transport, uid: &[u8]) -> Result<Vec<[u8; 8]>, ReaderError> {
+    let inventory = device.iso15693_inventory()?;
+    let target = inventory
+        .iter()
+        .position(|u| u[..] == *uid)
+        .ok_or(ReaderError::CommandError(StatusCode::NoCard))?;
+    if inventory.len() > 1 {
+        if let Err(e) = isolate(device, &inventory, target) {
+            release(device, &inventory);
+            return Err(e);
+        }
+    }
+    Ok(inventory)
+}
+
+// Runs a non-addressed command for one tag, see address()
+fn with_tag<T: Transport, R>(
+    device: &T,
+    uid: &[u8],
+    command: impl FnOnce(&T) -> Result<R, ReaderError>,
+) -> Result<R, ReaderError> {
+    let inventory = address(device, uid)?;
+    let result = command(device);
+    if inventory.len() > 1 {
+        release(device, &inventory);
+    }
+    result
+}
+
+// Reads every tag in the field. Tags without readable memory are skipped
+fn read_tags(device: &impl Transport, blocks: u8) -> Result<Vec<RawTag>, ReaderError> {
+    let inventory = device.iso15693_inventory()?;
+    let multiple = inventory.len() > 1;
+    let mut tags = Vec::with_capacity(inventory.len());
+    for (i, uid) in inventory.iter().enumerate() {
+        if multiple {
+            if let Err(e) = isolate(device, &inventory, i) {
+                warn!("Unable to address tag {}: {e}", to_hex_string(uid));
+                continue;
             }
+        }
+        let memory = read_user_memory(device, blocks);
+        if !memory.is_empty() {
+            // The driver doesn't report DSFID
+            tags.push(RawTag {
+                uid: uid.to_vec(),
+                dsfid: None,
+                memory,
+            });
+        }
+    }
+    if multiple {
+        release(device, &inventory);
+    }
+    Ok(tags)
+}
+
+// Writes the memory of every tag from the beginning
+fn write_memory(device: &impl Transport, tags: &[RawTag]) -> Vec<WriteResponse> {
+    let inventory = device.iso15693_inventory().unwrap_or_default();
+    let multiple = inventory.len() > 1;
+    let mut responses = Vec::with_capacity(tags.len());
+    for tag in tags {
+        // A tag without UID can be written only if it's the only one
+        let target = if tag.uid.is_empty() && tags.len() == 1 && inventory.len() == 1 {
+            Some(0)
         } else {
-            self.handle = CFRH320U93::open();
+            inventory.iter().position(|uid| uid[..] == tag.uid[..])
+        };
+        let target = match target {
+            Some(t) => t,
+            None => {
+                responses.push(write_error(
+                    &tag.uid,
+                    "Error during writing a card. Probably there's no cards nearby.",
+                ));
+                continue;
+            }
+        };
+        if multiple {
+            if let Err(e) = isolate(device, &inventory, target) {
+                warn!("Unable to address tag {}: {e}", to_hex_string(&tag.uid));
+                responses.push(write_error(&tag.uid, "Unable to address the tag"));
+                continue;
+            }
+        }
+        let uid = &inventory[target];
+        match device.iso15693_write(AccessFlag::WithoutUID, 0, &tag.memory) {
+            Ok(()) => responses.push(WriteResponse {
+                id: to_hex_string(uid),
+                success: true,
+                error: None,
+                locked: None,
+            }),
+            Err(e) => {
+                warn!("Unable to write tag {}: {e}", to_hex_string(uid));
+                responses.push(write_error(uid, "Error during writing a card"));
+            }
+        }
+    }
+    if multiple {
+        release(device, &inventory);
+    }
+    responses
+}
+
+// Locks blocks of one tag one by one, see with_tag()
+fn lock_tag_blocks(
+    device: &impl Transport,
+    uid: &[u8],
+    blocks: Range<u8>,
+) -> Result<(), ReaderError> {
+    with_tag(device, uid, |device| {
+        blocks
+            .into_iter()
+            .try_for_each(|block| device.iso15693_lock_block(AccessFlag::WithoutUID, block))
+    })
+}
+
+// Parses an answer to Get System Information (ISO/IEC 15693-3): information flags,
+// UID (LSB first), then the fields which are marked by the flags
+fn parse_system_info(uid: &[u8; 8], answer: &[u8]) -> Result<TagInfo, ReaderError> {
+    let invalid = ReaderError::CommandError(StatusCode::InvalidData);
+    let flags = *answer.first().ok_or(invalid)?;
+    let mut fields = answer.get(9..).ok_or(invalid)?.iter().copied();
+    let mut field = |flag: u8| match flags & flag {
+        0 => Ok(None),
+        _ => fields.next().map(Some).ok_or(invalid),
+    };
+    let dsfid = field(0x01)?;
+    let afi = field(0x02)?;
+    // Memory size: number of blocks - 1, then block size - 1 in the lower 5 bits
+    let block_count = field(0x04)?;
+    let block_size = field(0x04)?;
+    let ic_reference = field(0x08)?;
+    Ok(TagInfo {
+        // UIDs are kept as inventory reports them
+        uid: uid.to_vec(),
+        dsfid,
+        afi,
+        block_size: block_size.map(|size| (size & 0x1F) + 1),
+        block_count: block_count.map(|count| count as u16 + 1),
+        ic_reference,
+    })
+}
+
+fn system_info(device: &impl Transport, uid: &[u8; 8]) -> Result<TagInfo, ReaderError> {
+    let answer = with_tag(device, uid, |device| {
+        device.iso15693_get_system_info(AccessFlag::WithoutUID)
+    })?;
+    parse_system_info(uid, &answer)
+}
+
+// System information of a tag. None if it isn't in the field
+fn tag_info(device: &impl Transport, uid: &[u8]) -> Option<TagInfo> {
+    let uid: [u8; 8] = uid.try_into().ok()?;
+    system_info(device, &uid)
+        .map_err(|e| {
+            warn!(
+                "Unable to get system information of tag {}: {e}",
+                to_hex_string(&uid)
+            )
+        })
+        .ok()
+        .or_else(|| {
+            // Tags which don't support the command are only known to be present
+            let inventory = device.iso15693_inventory().ok()?;
+            inventory.contains(&uid).then(|| TagInfo {
+                uid: uid.to_vec(),
+                ..Default::default()
+            })
+        })
+}
+
+// Tags in the field with their AFI
+fn read_security(device: &impl Transport) -> Result<Vec<TagSecurity>, ReaderError> {
+    let inventory = device.iso15693_inventory()?;
+    Ok(inventory
+        .iter()
+        .map(|uid| TagSecurity {
+            tag_id: to_hex_string(uid),
+            afi: system_info(device, uid)
+                .map_err(|e| warn!("Unable to read AFI of tag {}: {e}", to_hex_string(uid)))
+                .ok()
+                .and_then(|info| info.afi),
+        })
+        .collect())
+}
+
+// Writes AFI of every tag by its id
+fn write_security(device: &impl Transport, params: HashMap<String, bool>) -> Vec<WriteResponse> {
+    params
+        .into_iter()
+        .map(|(id, is_secured)| {
+            let afi = if is_secured {
+                AFI_SECURED
+            } else {
+                AFI_CHECKED_OUT
+            };
+            let result = from_hex_string(&id)
+                .map_err(|_| ReaderError::CommandError(StatusCode::NoCard))
+                .and_then(|uid| {
+                    with_tag(device, &uid, |device| {
+                        device.iso15693_write_afi(AccessFlag::WithoutUID, afi)
+                    })
+                });
+            match result {
+                Ok(()) => WriteResponse {
+                    id,
+                    success: true,
+                    error: None,
+                    locked: None,
+                },
+                Err(e) => {
+                    warn!("Unable to write AFI of tag {id}: {e}");
+                    security_error(id, &e.to_string())
+                }
+            }
+        })
+        .collect()
+}
+
+impl Device for CfRh320u93 {
+    fn connect(&mut self) {
+        // Reopen the unit if there was an error
+        if !self.handle.as_ref().is_ok_and(|unit| unit.is_alive()) {
+            self.open();
         }
     }
 
@@ -32,82 +334,463 @@ impl Device for CfRh320u93 {
         self.handle.is_ok()
     }
 
+    fn error(&self) -> Option<String> {
+        match &self.handle {
+            // The reader is just unplugged
+            Ok(_) | Err(ReaderError::UsbError(rusb::Error::NoDevice)) => None,
+            Err(e) => Some(e.to_string()),
+        }
+    }
+
     fn multi_tag_is_supported(&self) -> bool {
-        false
+        true
     }
 
     fn compound_data_is_supported(&self) -> bool {
         true
     }
 
+    fn security_is_supported(&self) -> bool {
+        true
+    }
+
+    fn lock_is_supported(&self) -> bool {
+        true
+    }
+
+    fn tag_info_is_supported(&self) -> bool {
+        true
+    }
+
+    fn beep_is_supported(&self) -> bool {
+        true
+    }
+
     fn is_read_only(&self) -> bool {
         false
     }
 
-    fn get_items(&mut self) -> Vec<DanishRfidItem> {
+    fn get_tags(&mut self) -> Vec<RawTag> {
         if let Ok(device) = &self.handle {
-            let _ = device.control_led(0x01, 0x20);
-
-            if let Ok(inventory) = device.iso15693_inventory() {
-                // this reader supports reading only 1 card at a time
-                if inventory.len() == 1 {
-                    let bytes = device
-                        .iso15693_read(AccessFlag::WithoutUID, 0, 0x08)
-                        .unwrap_or(vec![]);
-                    if let Ok(mut item) = DanishRfidItem::from_bytes(&bytes) {
-                        item.set_card_id(inventory[0].to_vec());
-                        return vec![item];
-                    }
-                }
+            if self.options.led {
+                let _ = device.control_led(0x01, 0x20);
+            }
+
+            if let Ok(tags) = read_tags(device, self.options.read_blocks) {
+                return tags;
             }
         }
         vec![]
     }
 
-    fn write_tags(&mut self, items: Vec<DanishRfidItem>) -> Vec<WriteResponse> {
-        if let Ok(device) = &self.handle {
-            let _ = device.control_led(0x01, 0x10);
+    fn get_tags_security(&mut self) -> Vec<TagSecurity> {
+        match &self.handle {
+            Ok(device) => read_security(device).unwrap_or_default(),
+            Err(_) => vec![],
+        }
+    }
 
-            if items.len() != 1 {
-                let mut resps = Vec::with_capacity(items.len());
-                for item in items {
-                    resps.push(WriteResponse {
-                        id: item.card_id_string(),
-                        success: false,
-                        error: Some(WriteError {
-                            r#type: "Write Error".to_string(),
-                            message: "Reader can write only one tag at a time".to_string(),
-                        }),
-                    });
-                }
-                return resps;
-            }
-            let item = items[0].to_bytes();
-            if let Ok(()) = device.iso15693_write(AccessFlag::WithoutUID, 0, &item) {
-                return vec![WriteResponse {
-                    id: items[0].card_id_string(),
-                    success: true,
-                    error: None,
-                }];
-            } else {
-                return vec![WriteResponse {
-                    id: items[0].card_id_string(),
-                    success: false,
-                    error: Some(WriteError {
-                        r#type: "Write Error".to_string(),
-                        message: "Error during writing a card. Probably there's no cards nearby."
-                            .to_string(),
-                    }),
-                }];
+    fn get_tag_info(&mut self, uid: &[u8]) -> Option<TagInfo> {
+        tag_info(self.handle.as_ref().ok()?, uid)
+    }
+
+    fn set_tags_security(&mut self, params: HashMap<String, bool>) -> Vec<WriteResponse> {
+        match &self.handle {
+            Ok(device) => write_security(device, params),
+            Err(_) => params
+                .into_keys()
+                .map(|id| security_error(id, "Couldn't connect to the reader"))
+                .collect(),
+        }
+    }
+
+    fn write_tags(&mut self, tags: Vec<RawTag>) -> Vec<WriteResponse> {
+        let device = match &self.handle {
+            Ok(device) => device,
+            Err(_) => {
+                return tags
+                    .iter()
+                    .map(|tag| write_error(&tag.uid, "Couldn't connect to the reader"))
+                    .collect()
             }
+        };
+        if self.options.led {
+            let _ = device.control_led(0x01, 0x10);
         }
-        vec![WriteResponse {
-            id: items[0].card_id_string(),
-            success: false,
-            error: Some(WriteError {
-                r#type: "Write Error".to_string(),
-                message: "Couldn't connect to the reader".to_string(),
-            }),
-        }]
+
+        let responses = write_memory(device, &tags);
+        if self.options.buzzer && responses.iter().any(|r| r.success) {
+            let _ = device.control_buzzer(0x01, 0x10);
+        }
+        responses
+    }
+
+    fn read_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<Vec<u8>, WriteError> {
+        let device = self.handle.as_ref().map_err(|e| raw_error(*e))?;
+        with_tag(device, uid, |device| {
+            device.iso15693_read(AccessFlag::WithoutUID, blocks.start, blocks.len() as u8)
+        })
+        .map_err(raw_error)
+    }
+
+    fn write_blocks(&mut self, uid: &[u8], first_block: u8, data: &[u8]) -> Result<(), WriteError> {
+        let device = self.handle.as_ref().map_err(|e| raw_error(*e))?;
+        with_tag(device, uid, |device| {
+            device.iso15693_write(AccessFlag::WithoutUID, first_block, data)
+        })
+        .map_err(raw_error)
+    }
+
+    fn lock_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<(), WriteError> {
+        let device = self.handle.as_ref().map_err(|e| raw_error(*e))?;
+        lock_tag_blocks(device, uid, blocks).map_err(raw_error)
+    }
+
+    fn beep(&mut self) -> Result<(), WriteError> {
+        let device = self.handle.as_ref().map_err(|e| raw_error(*e))?;
+        device.control_buzzer(0x01, 0x10).map_err(raw_error)
+    }
+}
+
+fn raw_error(e: ReaderError) -> WriteError {
+    WriteError {
+        r#type: "Reader Error".to_string(),
+        message: e.to_string(),
+    }
+}
+
+fn security_error(id: String, message: &str) -> WriteResponse {
+    WriteResponse {
+        id,
+        success: false,
+        error: Some(WriteError {
+            r#type: "Security Error".to_string(),
+            message: message.to_string(),
+        }),
+        locked: None,
+    }
+}
+
+fn write_error(uid: &[u8], message: &str) -> WriteResponse {
+    WriteResponse {
+        id: to_hex_string(uid),
+        success: false,
+        error: Some(WriteError {
+            r#type: "Write Error".to_string(),
+            message: message.to_string(),
+        }),
+        locked: None,
+    }
+}
+
+// Tags in the field of a virtual reader. Non-addressed commands reach every tag which
+// isn't quiet, so a write which isn't isolated changes other tags too
+#[cfg(test)]
+struct VirtualField {
+    tags: std::cell::RefCell<Vec<VirtualTag>>,
+    // UIDs of tags which don't answer Stay Quiet
+    deaf: Vec<[u8; 8]>,
+}
+
+#[cfg(test)]
+struct VirtualTag {
+    uid: [u8; 8],
+    is_quiet: bool,
+    afi: u8,
+    memory: Vec<u8>,
+    locked: Vec<u8>,
+}
+
+#[cfg(test)]
+impl VirtualField {
+    fn new(count: u8) -> Self {
+        let tags = (1..=count)
+            .map(|i| VirtualTag {
+                uid: [0xE0, 0x04, 0x01, 0x50, 0, 0, 0, i],
+                is_quiet: false,
+                afi: AFI_CHECKED_OUT,
+                memory: vec![i; 32],
+                locked: vec![],
+            })
+            .collect();
+        Self {
+            tags: std::cell::RefCell::new(tags),
+            deaf: vec![],
+        }
+    }
+
+    fn memory(&self, i: usize) -> Vec<u8> {
+        self.tags.borrow()[i].memory.clone()
+    }
+
+    fn uid(&self, i: usize) -> [u8; 8] {
+        self.tags.borrow()[i].uid
+    }
+
+    fn afi(&self, i: usize) -> u8 {
+        self.tags.borrow()[i].afi
     }
+
+    fn all_answer(&self) -> bool {
+        self.tags.borrow().iter().all(|t| !t.is_quiet)
+    }
+}
+
+#[cfg(test)]
+impl Transport for VirtualField {
+    fn iso15693_inventory(&self) -> Result<Vec<[u8; 8]>, ReaderError> {
+        let tags = self.tags.borrow();
+        Ok(tags.iter().filter(|t| !t.is_quiet).map(|t| t.uid).collect())
+    }
+    fn iso15693_read(&self, _: AccessFlag, skip: u8, blocks: u8) -> Result<Vec<u8>, ReaderError> {
+        let tags = self.tags.borrow();
+        let answering: Vec<_> = tags.iter().filter(|t| !t.is_quiet).collect();
+        let bytes = skip as usize * 4..(skip + blocks) as usize * 4;
+        match answering[..] {
+            [tag] => tag.memory.get(bytes).map(|m| m.to_vec()),
+            // Answers of several tags collide
+            _ => None,
+        }
+        .ok_or(ReaderError::CommandError(StatusCode::Failure))
+    }
+    fn iso15693_write(&self, _: AccessFlag, skip: u8, data: &[u8]) -> Result<(), ReaderError> {
+        let start = skip as usize * 4;
+        let blocks = skip..skip + data.len().div_ceil(4) as u8;
+        let mut tags = self.tags.borrow_mut();
+        let mut answering: Vec<_> = tags.iter_mut().filter(|t| !t.is_quiet).collect();
+        if answering
+            .iter()
+            .any(|t| blocks.clone().any(|b| t.locked.contains(&b)))
+        {
+            return Err(ReaderError::CommandError(StatusCode::BlockIsLocked));
+        }
+        for tag in answering.iter_mut() {
+            tag.memory[start..start + data.len()].copy_from_slice(data);
+        }
+        Ok(())
+    }
+    fn iso15693_select(&self, _: AccessFlag, uid: &[u8; 8]) -> Result<(), ReaderError> {
+        let mut tags = self.tags.borrow_mut();
+        let tag = tags.iter_mut().find(|t| t.uid == *uid);
+        tag.map(|t| t.is_quiet = false)
+            .ok_or(ReaderError::CommandError(StatusCode::NoCard))
+    }
+    fn iso15693_stay_quiet(&self, _: AccessFlag, uid: &[u8; 8]) -> Result<(), ReaderError> {
+        if self.deaf.contains(uid) {
+            return Err(ReaderError::CommandError(StatusCode::Failure));
+        }
+        let mut tags = self.tags.borrow_mut();
+        let tag = tags.iter_mut().find(|t| t.uid == *uid);
+        tag.map(|t| t.is_quiet = true)
+            .ok_or(ReaderError::CommandError(StatusCode::NoCard))
+    }
+    fn iso15693_lock_block(&self, _: AccessFlag, block: u8) -> Result<(), ReaderError> {
+        for tag in self.tags.borrow_mut().iter_mut().filter(|t| !t.is_quiet) {
+            tag.locked.push(block);
+        }
+        Ok(())
+    }
+    fn iso15693_write_afi(&self, _: AccessFlag, afi: u8) -> Result<(), ReaderError> {
+        for tag in self.tags.borrow_mut().iter_mut().filter(|t| !t.is_quiet) {
+            tag.afi = afi;
+        }
+        Ok(())
+    }
+    fn iso15693_get_system_info(&self, _: AccessFlag) -> Result<Vec<u8>, ReaderError> {
+        let tags = self.tags.borrow();
+        let answering: Vec<_> = tags.iter().filter(|t| !t.is_quiet).collect();
+        let [tag] = answering[..] else {
+            return Err(ReaderError::CommandError(StatusCode::Failure));
+        };
+        // All fields: DSFID, AFI, memory size and IC reference
+        let mut answer = vec![0x0F];
+        answer.extend(tag.uid.iter().rev());
+        answer.extend([0x00, tag.afi, (tag.memory.len() / 4 - 1) as u8, 0x03, 0x01]);
+        Ok(answer)
+    }
+}
+
+#[test]
+fn tags_are_read_one_by_one() {
+    let field = VirtualField::new(3);
+    let tags = read_tags(&field, PRIMARY_BLOCKS).unwrap();
+    assert_eq!(tags.len(), 3);
+    for (i, tag) in tags.iter().enumerate() {
+        assert_eq!(tag.uid, field.uid(i));
+        assert_eq!(tag.memory, field.memory(i));
+    }
+    assert!(field.all_answer());
+}
+
+#[test]
+fn only_addressed_tags_are_written() {
+    let field = VirtualField::new(3);
+    let uid = field.uid(1);
+    with_tag(&field, &uid, |d| {
+        d.iso15693_write(AccessFlag::WithoutUID, 1, &[0xBB; 4])
+    })
+    .unwrap();
+    assert_eq!(field.memory(0), vec![1; 32]);
+    assert_eq!(field.memory(1)[..8], [2, 2, 2, 2, 0xBB, 0xBB, 0xBB, 0xBB]);
+    assert_eq!(field.memory(2), vec![3; 32]);
+    assert!(field.all_answer());
+}
+
+#[test]
+fn several_tags_are_written_by_their_uids() {
+    let field = VirtualField::new(3);
+    let tags = [
+        RawTag {
+            uid: field.uid(0).to_vec(),
+            dsfid: None,
+            memory: vec![0xAA; 32],
+        },
+        RawTag {
+            uid: field.uid(2).to_vec(),
+            dsfid: None,
+            memory: vec![0xCC; 32],
+        },
+    ];
+    let responses = write_memory(&field, &tags);
+    assert!(responses.iter().all(|r| r.success));
+    assert_eq!(field.memory(0), vec![0xAA; 32]);
+    assert_eq!(field.memory(1), vec![2; 32]);
+    assert_eq!(field.memory(2), vec![0xCC; 32]);
+    assert!(field.all_answer());
+}
+
+#[test]
+fn tags_are_released_if_addressing_fails() {
+    let mut field = VirtualField::new(3);
+    field.deaf.push(field.uid(2));
+
+    // Tag 2 would be written with tag 0, so nothing is written
+    let uid = field.uid(0);
+    let result = with_tag(&field, &uid, |d| {
+        d.iso15693_write(AccessFlag::WithoutUID, 0, &[0xAA; 4])
+    });
+    assert!(result.is_err());
+    let tags = [RawTag {
+        uid: uid.to_vec(),
+        dsfid: None,
+        memory: vec![0xAA; 32],
+    }];
+    assert!(!write_memory(&field, &tags)[0].success);
+    for i in 0..3 {
+        assert_eq!(field.memory(i), vec![i as u8 + 1; 32]);
+    }
+    assert!(field.all_answer());
+}
+
+#[test]
+fn security_of_addressed_tags_is_written() {
+    let field = VirtualField::new(3);
+    let params = HashMap::from([
+        (to_hex_string(&field.uid(1)), true),
+        ("E004015000000009".to_string(), true),
+    ]);
+    let mut responses = write_security(&field, params);
+    responses.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(responses[0].id, to_hex_string(&field.uid(1)));
+    assert!(responses[0].success);
+    // The tag isn't in the field
+    assert!(!responses[1].success);
+    assert_eq!(field.afi(0), AFI_CHECKED_OUT);
+    assert_eq!(field.afi(1), AFI_SECURED);
+    assert_eq!(field.afi(2), AFI_CHECKED_OUT);
+    assert!(field.all_answer());
+}
+
+#[test]
+fn security_of_every_tag_is_read() {
+    let field = VirtualField::new(3);
+    write_security(
+        &field,
+        HashMap::from([(to_hex_string(&field.uid(2)), true)]),
+    );
+    let security = read_security(&field).unwrap();
+    let afis: Vec<_> = security.iter().map(|tag| tag.afi).collect();
+    assert_eq!(
+        afis,
+        [
+            Some(AFI_CHECKED_OUT),
+            Some(AFI_CHECKED_OUT),
+            Some(AFI_SECURED)
+        ]
+    );
+    assert_eq!(security[2].tag_id, to_hex_string(&field.uid(2)));
+    assert!(field.all_answer());
+}
+
+#[test]
+fn system_info_fields() {
+    let uid = [0xE0, 0x04, 0x01, 0x50, 0x12, 0x34, 0x56, 0x78];
+    let mut answer = vec![0x0F];
+    answer.extend(uid.iter().rev());
+    answer.extend([0x00, AFI_SECURED, 0x1B, 0x03, 0x01]);
+    let info = parse_system_info(&uid, &answer).unwrap();
+    assert_eq!(info.uid, uid);
+    assert_eq!((info.dsfid, info.afi), (Some(0x00), Some(AFI_SECURED)));
+    assert_eq!((info.block_size, info.block_count), (Some(4), Some(28)));
+    assert_eq!(info.ic_reference, Some(0x01));
+
+    // Only AFI is reported
+    let mut answer = vec![0x02];
+    answer.extend(uid.iter().rev());
+    answer.push(AFI_CHECKED_OUT);
+    let info = parse_system_info(&uid, &answer).unwrap();
+    assert_eq!((info.dsfid, info.afi), (None, Some(AFI_CHECKED_OUT)));
+    assert_eq!((info.block_size, info.ic_reference), (None, None));
+
+    // A field marked by the flags is missing
+    assert!(parse_system_info(&uid, &answer[..9]).is_err());
+    assert!(parse_system_info(&uid, &[]).is_err());
+}
+
+#[test]
+fn locked_blocks_refuse_writes() {
+    let field = VirtualField::new(3);
+    let uid = field.uid(1);
+    lock_tag_blocks(&field, &uid, 0..PRIMARY_BLOCKS).unwrap();
+    assert!(field.all_answer());
+
+    let write = |i: usize, block: u8| {
+        let uid = field.uid(i);
+        with_tag(&field, &uid, |d| {
+            d.iso15693_write(AccessFlag::WithoutUID, block, &[0xBB; 4])
+        })
+    };
+    assert!(matches!(
+        write(1, 0),
+        Err(ReaderError::CommandError(StatusCode::BlockIsLocked))
+    ));
+    assert!(write(1, PRIMARY_BLOCKS - 1).is_err());
+    assert_eq!(field.memory(1), vec![2; 32]);
+    // Other tags aren't locked
+    assert!(write(0, 0).is_ok());
+    assert_eq!(field.memory(0)[..8], [0xBB, 0xBB, 0xBB, 0xBB, 1, 1, 1, 1]);
+
+    let tags = [RawTag {
+        uid: uid.to_vec(),
+        dsfid: None,
+        memory: vec![0xCC; 32],
+    }];
+    assert!(!write_memory(&field, &tags)[0].success);
+    assert_eq!(field.memory(1), vec![2; 32]);
+    assert!(field.all_answer());
+}
+
+#[test]
+fn tag_info_of_addressed_tag() {
+    let field = VirtualField::new(3);
+    let uid = field.uid(1);
+    let info = tag_info(&field, &uid).unwrap();
+    assert_eq!(info.uid, uid);
+    assert_eq!((info.dsfid, info.afi), (Some(0x00), Some(AFI_CHECKED_OUT)));
+    assert_eq!((info.block_size, info.block_count), (Some(4), Some(8)));
+    assert_eq!(info.ic_reference, Some(0x01));
+    assert!(field.all_answer());
+
+    assert!(tag_info(&field, &[0xE0, 0x04, 0x01, 0x50, 0, 0, 0, 9]).is_none());
+    assert!(tag_info(&field, &[0xE0]).is_none());
 }
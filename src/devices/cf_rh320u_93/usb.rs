@@ -0,0 +1,287 @@
+// USB connection to a unit of the reader. The driver crate opens only the first
+// attached unit, so units are opened here by their port paths. The commands are
+// the same as in the driver, and its types are used for the results
+use super::Transport;
+use cf_rh320u_93_reader::{AccessFlag, ReaderError, StatusCode};
+use log::*;
+use rusb::{Context, Device, DeviceDescriptor, DeviceHandle, UsbContext};
+use std::time::Duration;
+
+// USB ids of the reader, the same as in the driver
+const VID: u16 = 0xffff;
+const PID: u16 = 0x0035;
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Every command is sent as a HID report of this size, which starts with the header
+const REPORT_SIZE: usize = 256;
+const REPORT_HEADER: [u8; 10] = [0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0xaa, 0x00];
+const REPORT_END: u8 = 0xbb;
+
+// Commands start with a length byte and a command code. The length bytes are
+// the same as in the driver, some of them don't match the actual length
+const CMD_CONTROL_LED: [u8; 2] = [0x03, 0x88];
+const CMD_CONTROL_BUZZER: [u8; 2] = [0x03, 0x89];
+const CMD_ISO15693_INVENTORY: [u8; 2] = [0x04, 0x10];
+const CMD_ISO15693_READ: [u8; 2] = [0x04, 0x11];
+const CMD_ISO15693_WRITE: [u8; 2] = [0x10, 0x12];
+const CMD_ISO15693_STAY_QUIET: [u8; 2] = [0x10, 0x14];
+const CMD_ISO15693_SELECT: [u8; 2] = [0x0a, 0x15];
+// Commands which the driver doesn't have. Their codes are those of the vendor's
+// library (ISO15693_Lock etc.), the reader relays them to tags as ISO 15693
+// Lock Block (0x22), Write AFI (0x27) and Get System Information (0x2B).
+// Write EAS is a custom command of NXP chips, which isn't relayed
+const CMD_ISO15693_LOCK_BLOCK: [u8; 2] = [0x03, 0x13];
+const CMD_ISO15693_WRITE_AFI: [u8; 2] = [0x03, 0x17];
+const CMD_ISO15693_GET_SYSTEM_INFO: [u8; 2] = [0x02, 0x1b];
+// Value of the byte of an answer which tells that no tag has answered
+const NO_TAG: u8 = 0x01;
+
+// An attached unit as it's found on the bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitInfo {
+    // USB port path (bus-port.port...). It's the same while a unit is plugged into the same port
+    pub path: String,
+    // USB serial number. None if a unit doesn't have it or it isn't unique
+    pub serial: Option<String>,
+}
+
+impl UnitInfo {
+    // Stable id of a unit: the serial number, which follows it to another port, or the path
+    pub fn id(&self) -> &str {
+        self.serial.as_deref().unwrap_or(&self.path)
+    }
+}
+
+// Lists attached units. Units of the same batch may share a serial number,
+// so such numbers aren't used to tell them apart
+pub fn units() -> Vec<UnitInfo> {
+    let devices = match Context::new().and_then(|c| c.devices()) {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("Unable to enumerate USB devices: {e}");
+            return vec![];
+        }
+    };
+    let mut units: Vec<UnitInfo> = devices
+        .iter()
+        .filter_map(|device| {
+            let desc = device
+                .device_descriptor()
+                .ok()
+                .filter(|desc| desc.vendor_id() == VID && desc.product_id() == PID)?;
+            Some(UnitInfo {
+                path: port_path(&device),
+                serial: serial_number(&device, &desc),
+            })
+        })
+        .collect();
+    let serials: Vec<Option<String>> = units.iter().map(|u| u.serial.clone()).collect();
+    for unit in units.iter_mut() {
+        if serials.iter().filter(|s| **s == unit.serial).count() > 1 {
+            unit.serial = None;
+        }
+    }
+    units
+}
+
+fn port_path(device: &Device<Context>) -> String {
+    let ports: Vec<String> = device
+        .port_numbers()
+        .unwrap_or_default()
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    format!("{}-{}", device.bus_number(), ports.join("."))
+}
+
+fn serial_number(device: &Device<Context>, desc: &DeviceDescriptor) -> Option<String> {
+    desc.serial_number_string_index()?;
+    let handle = device.open().ok()?;
+    let language = *handle.read_languages(TIMEOUT).ok()?.first()?;
+    handle
+        .read_serial_number_string(language, desc, TIMEOUT)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// An opened unit. The interface is released when it's dropped
+pub struct Unit {
+    handle: DeviceHandle<Context>,
+    interface: u8,
+    has_kernel_driver: bool,
+}
+
+impl Unit {
+    pub fn open(path: &str) -> Result<Self, ReaderError> {
+        let context = Context::new()?;
+        let device = context
+            .devices()?
+            .iter()
+            .find(|d| {
+                d.device_descriptor()
+                    .is_ok_and(|desc| desc.vendor_id() == VID && desc.product_id() == PID)
+                    && port_path(d) == path
+            })
+            .ok_or(rusb::Error::NoDevice)?;
+        let mut handle = device.open()?;
+
+        // The first interface with an endpoint is used, as the driver does
+        let (config, interface, setting) = (0..device.device_descriptor()?.num_configurations())
+            .filter_map(|n| device.config_descriptor(n).ok())
+            .find_map(|config| {
+                config
+                    .interfaces()
+                    .flat_map(|i| i.descriptors())
+                    .find(|d| d.num_endpoints() > 0)
+                    .map(|d| (config.number(), d.interface_number(), d.setting_number()))
+            })
+            .ok_or(rusb::Error::NoDevice)?;
+        // Windows requires interface 1 for this type of HID device
+        let interface = if cfg!(windows) { 1 } else { interface };
+
+        let has_kernel_driver = matches!(handle.kernel_driver_active(interface), Ok(true));
+        if has_kernel_driver {
+            handle.detach_kernel_driver(interface)?;
+        }
+        // The kernel driver is attached back if the unit can't be claimed
+        let mut unit = Self {
+            handle,
+            interface,
+            has_kernel_driver,
+        };
+        unit.handle.set_active_configuration(config)?;
+        unit.handle.claim_interface(interface)?;
+        unit.handle.set_alternate_setting(interface, setting)?;
+        Ok(unit)
+    }
+
+    // Whether the unit is still plugged in
+    pub fn is_alive(&self) -> bool {
+        self.handle.read_languages(TIMEOUT).is_ok()
+    }
+
+    // Makes the LED glow green `count` times for `duration`
+    pub fn control_led(&self, count: u8, duration: u8) -> Result<(), ReaderError> {
+        let answer = self.command(CMD_CONTROL_LED, &[duration, count])?;
+        check_status(answer[12])
+    }
+
+    // Makes the buzzer sound `count` times for `duration`
+    pub fn control_buzzer(&self, count: u8, duration: u8) -> Result<(), ReaderError> {
+        let answer = self.command(CMD_CONTROL_BUZZER, &[duration, count])?;
+        check_status(answer[12])
+    }
+
+    // Sends a command and returns the answer without its checksum and end byte
+    fn command(&self, command: [u8; 2], params: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        let mut report = REPORT_HEADER.to_vec();
+        report.extend(command);
+        report.extend(params);
+        let checksum = report[10..].iter().fold(0, |sum, b| sum ^ b);
+        report.extend([checksum, REPORT_END]);
+        report.resize(REPORT_SIZE, 0);
+        self.handle
+            .write_control(0x21, 0x09, 0x0301, 1, &report, TIMEOUT)?;
+
+        let mut answer = [0u8; REPORT_SIZE];
+        self.handle
+            .read_control(0xa1, 0x01, 0x0302, 1, &mut answer, TIMEOUT)?;
+        let len = answer.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        // Every answer has a status after the header and the command code
+        if len < 15 {
+            return Err(StatusCode::InvalidData.into());
+        }
+        Ok(answer[..len - 2].to_vec())
+    }
+
+    // Sends an ISO 15693 command, which fails if no tag has answered
+    fn tag_command(&self, command: [u8; 2], params: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        let answer = self.command(command, params)?;
+        match answer[11] {
+            NO_TAG => Err(StatusCode::from(answer[12]).into()),
+            _ => Ok(answer),
+        }
+    }
+}
+
+fn check_status(status: u8) -> Result<(), ReaderError> {
+    match StatusCode::from(status) {
+        StatusCode::Ok => Ok(()),
+        code => Err(code.into()),
+    }
+}
+
+impl Transport for Unit {
+    fn iso15693_inventory(&self) -> Result<Vec<[u8; 8]>, ReaderError> {
+        let answer = self.tag_command(CMD_ISO15693_INVENTORY, &[0x06, 0x00, 0x00])?;
+        // Every UID is followed by two bytes
+        let count = answer[12] as usize;
+        (0..count)
+            .map(|i| {
+                answer
+                    .get(15 + 10 * i..23 + 10 * i)
+                    .and_then(|uid| uid.try_into().ok())
+                    .ok_or(StatusCode::InvalidData.into())
+            })
+            .collect()
+    }
+
+    fn iso15693_read(
+        &self,
+        flag: AccessFlag,
+        skip: u8,
+        blocks: u8,
+    ) -> Result<Vec<u8>, ReaderError> {
+        let answer = self.tag_command(CMD_ISO15693_READ, &[flag as u8, skip, blocks])?;
+        Ok(answer[13..].to_vec())
+    }
+
+    fn iso15693_write(&self, flag: AccessFlag, skip: u8, data: &[u8]) -> Result<(), ReaderError> {
+        // Only whole blocks are written, the last one is padded with zeros
+        let blocks = data.len().div_ceil(4);
+        let mut params = vec![flag as u8, skip, blocks as u8];
+        params.extend(data);
+        params.resize(3 + blocks * 4, 0);
+        self.tag_command(CMD_ISO15693_WRITE, &params).map(|_| ())
+    }
+
+    fn iso15693_select(&self, flag: AccessFlag, uid: &[u8; 8]) -> Result<(), ReaderError> {
+        let mut params = vec![flag as u8];
+        params.extend(uid);
+        let answer = self.tag_command(CMD_ISO15693_SELECT, &params)?;
+        check_status(answer[12])
+    }
+
+    fn iso15693_stay_quiet(&self, flag: AccessFlag, uid: &[u8; 8]) -> Result<(), ReaderError> {
+        let mut params = vec![flag as u8];
+        params.extend(uid);
+        let answer = self.tag_command(CMD_ISO15693_STAY_QUIET, &params)?;
+        check_status(answer[12])
+    }
+
+    fn iso15693_lock_block(&self, flag: AccessFlag, block: u8) -> Result<(), ReaderError> {
+        let answer = self.tag_command(CMD_ISO15693_LOCK_BLOCK, &[flag as u8, block])?;
+        check_status(answer[12])
+    }
+
+    fn iso15693_write_afi(&self, flag: AccessFlag, afi: u8) -> Result<(), ReaderError> {
+        let answer = self.tag_command(CMD_ISO15693_WRITE_AFI, &[flag as u8, afi])?;
+        check_status(answer[12])
+    }
+
+    fn iso15693_get_system_info(&self, flag: AccessFlag) -> Result<Vec<u8>, ReaderError> {
+        // The answer of the tag follows the status as in Read
+        let answer = self.tag_command(CMD_ISO15693_GET_SYSTEM_INFO, &[flag as u8])?;
+        Ok(answer[13..].to_vec())
+    }
+}
+
+impl Drop for Unit {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface);
+        if self.has_kernel_driver {
+            let _ = self.handle.attach_kernel_driver(self.interface);
+        }
+    }
+}
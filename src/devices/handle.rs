@@ -0,0 +1,292 @@
+use super::{Device, RawTag};
+use log::*;
+use rocket::tokio::sync::oneshot;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+// A command returns its reply, which is sent after the status of the device is updated
+type Reply = Box<dyn FnOnce() + Send>;
+type Run = Box<dyn FnOnce(&mut dyn Device) -> Reply + Send>;
+
+struct Command {
+    run: Run,
+    // Keeps the reply channel open until the status is updated, even if `run` panics
+    reply_guard: Box<dyn Send>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceState {
+    Connected,
+    Disconnected,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceStatus {
+    pub state: DeviceState,
+    // When the device has come to this state
+    pub since: SystemTime,
+    pub error: Option<String>,
+}
+
+// Features of a device which don't change while it's running
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceFeatures {
+    pub multi_tag_is_supported: bool,
+    pub compound_data_is_supported: bool,
+    pub is_read_only: bool,
+    pub security_is_supported: bool,
+    pub lock_is_supported: bool,
+    pub tag_info_is_supported: bool,
+    pub beep_is_supported: bool,
+}
+
+// Tags which were in the reader field at `time`
+#[derive(Debug)]
+pub struct Inventory {
+    pub tags: Vec<RawTag>,
+    pub time: Instant,
+}
+
+// A device lives on its own thread, so USB I/O never happens concurrently
+// no matter how many requests are served at once. Commands are run one by one.
+// When there are no commands, the thread checks the connection every `check_interval`,
+// so readers are reconnected after they're plugged in again
+pub struct DeviceHandle {
+    title: String,
+    commands: mpsc::Sender<Command>,
+    features: DeviceFeatures,
+    status: Arc<Mutex<DeviceStatus>>,
+    // The latest inventory. None if it's unknown or may be outdated
+    inventory: Arc<Mutex<Option<Arc<Inventory>>>>,
+}
+
+impl DeviceHandle {
+    pub fn spawn(title: &str, mut device: Box<dyn Device>, check_interval: Duration) -> Self {
+        let features = DeviceFeatures {
+            multi_tag_is_supported: device.multi_tag_is_supported(),
+            compound_data_is_supported: device.compound_data_is_supported(),
+            is_read_only: device.is_read_only(),
+            security_is_supported: device.security_is_supported(),
+            lock_is_supported: device.lock_is_supported(),
+            tag_info_is_supported: device.tag_info_is_supported(),
+            beep_is_supported: device.beep_is_supported(),
+        };
+        let status = Arc::new(Mutex::new(DeviceStatus {
+            state: DeviceState::Disconnected,
+            since: SystemTime::now(),
+            error: None,
+        }));
+
+        let (commands, receiver) = mpsc::channel::<Command>();
+        let thread_name = title.to_string();
+        let thread_status = status.clone();
+        thread::spawn(move || {
+            let mut check = true;
+            loop {
+                let (run, reply_guard): (Run, _) = if check {
+                    let check: Run = Box::new(|device| {
+                        device.connect();
+                        Box::new(|| ())
+                    });
+                    (check, None)
+                } else {
+                    match receiver.recv_timeout(check_interval) {
+                        Ok(command) => (command.run, Some(command.reply_guard)),
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            check = true;
+                            continue;
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                };
+                check = false;
+
+                // A panicking driver fails only the current request
+                let result = panic::catch_unwind(AssertUnwindSafe(|| run(&mut *device)));
+                let (state, error) = match result {
+                    Err(_) => (DeviceState::Error, Some("Driver has panicked".to_string())),
+                    Ok(_) => match device.error() {
+                        Some(e) => (DeviceState::Error, Some(e)),
+                        None if device.is_connected() => (DeviceState::Connected, None),
+                        None => (DeviceState::Disconnected, None),
+                    },
+                };
+                {
+                    let mut status = thread_status.lock().unwrap();
+                    if status.state != state || status.error != error {
+                        match &error {
+                            Some(e) => error!("Device {thread_name}: {e}"),
+                            None => info!("Device {thread_name} is {state:?}"),
+                        }
+                        *status = DeviceStatus {
+                            state,
+                            since: SystemTime::now(),
+                            error,
+                        };
+                    }
+                }
+                if let Ok(reply) = result {
+                    reply();
+                }
+                drop(reply_guard);
+            }
+        });
+        Self {
+            title: title.to_string(),
+            commands,
+            features,
+            status,
+            inventory: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Name of the device shown to users
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn features(&self) -> DeviceFeatures {
+        self.features
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    // Runs a function on the device thread and waits for its result.
+    // The cached inventory is dropped, since the function may change tags.
+    // None if the function has panicked or the thread has stopped
+    pub async fn run<R, F>(&self, f: F) -> Option<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn Device) -> R + Send + 'static,
+    {
+        let inventory = self.inventory.clone();
+        self.execute(move |device| {
+            *inventory.lock().unwrap() = None;
+            f(device)
+        })
+        .await
+    }
+
+    // Tags in the reader field read not earlier than `max_age` ago.
+    // Requests waiting for the same read share its result. None if the device isn't connected
+    pub async fn inventory(&self, max_age: Duration) -> Option<Arc<Inventory>> {
+        // None if max_age is longer than the uptime, so any read fits
+        let oldest = Instant::now().checked_sub(max_age);
+        let is_fresh = move |inventory: &Inventory| oldest.is_none_or(|t| inventory.time >= t);
+        if let Some(inventory) = self.inventory.lock().unwrap().as_ref() {
+            if is_fresh(inventory) {
+                return Some(inventory.clone());
+            }
+        }
+
+        let cache = self.inventory.clone();
+        let inventory = self
+            .execute(move |device| {
+                if let Some(inventory) = cache.lock().unwrap().as_ref() {
+                    if is_fresh(inventory) {
+                        return Some(inventory.clone());
+                    }
+                }
+                device.connect();
+                if !device.is_connected() {
+                    return None;
+                }
+                let tags = device.get_tags();
+                let inventory = Arc::new(Inventory {
+                    tags,
+                    time: Instant::now(),
+                });
+                *cache.lock().unwrap() = Some(inventory.clone());
+                Some(inventory)
+            })
+            .await;
+        // A failing driver is reported by status()
+        inventory.flatten()
+    }
+
+    // None if the command has panicked or the thread has stopped
+    async fn execute<R, F>(&self, f: F) -> Option<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn Device) -> R + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        let reply_sender = sender.clone();
+        let command = Command {
+            reply_guard: Box::new(ReplyGuard(sender)),
+            run: Box::new(move |device| {
+                let result = f(device);
+                Box::new(move || {
+                    if let Some(sender) = reply_sender.lock().unwrap().take() {
+                        let _ = sender.send(Some(result));
+                    }
+                })
+            }),
+        };
+        if self.commands.send(command).is_err() {
+            error!("Device {}: thread has stopped", self.title);
+            return None;
+        }
+        receiver.await.ok().flatten()
+    }
+}
+
+// Answers None when it's dropped, unless the result has been sent
+struct ReplyGuard<R>(Arc<Mutex<Option<oneshot::Sender<Option<R>>>>>);
+
+impl<R> Drop for ReplyGuard<R> {
+    fn drop(&mut self) {
+        if let Some(sender) = self.0.lock().unwrap().take() {
+            let _ = sender.send(None);
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn inventory_cache() {
+    let handle = DeviceHandle::spawn(
+        "Test Device",
+        Box::new(super::test_device::TestDevice::default()),
+        Duration::from_secs(1),
+    );
+    let first = handle.inventory(Duration::ZERO).await.unwrap();
+    assert_eq!(first.tags.len(), 1);
+
+    let cached = handle.inventory(Duration::from_secs(60)).await.unwrap();
+    assert!(Arc::ptr_eq(&first, &cached));
+    let fresh = handle.inventory(Duration::ZERO).await.unwrap();
+    assert!(!Arc::ptr_eq(&first, &fresh));
+
+    // Direct access to the device makes the cache outdated
+    handle.run(|device| device.connect()).await;
+    let after_run = handle.inventory(Duration::from_secs(60)).await.unwrap();
+    assert!(!Arc::ptr_eq(&fresh, &after_run));
+}
+
+#[rocket::async_test]
+async fn status_after_panic() {
+    let handle = DeviceHandle::spawn(
+        "Test Device",
+        Box::new(super::test_device::TestDevice::default()),
+        Duration::from_secs(1),
+    );
+    assert_eq!(handle.run(|device| device.connect()).await, Some(()));
+    assert_eq!(handle.status().state, DeviceState::Connected);
+
+    // The request fails instead of panicking, and the status is already updated
+    let result = handle.run(|_| panic!("Driver failure")).await;
+    assert_eq!(result, None::<()>);
+    assert_eq!(handle.status().state, DeviceState::Error);
+
+    // The thread is still alive and the device recovers
+    assert_eq!(handle.run(|device| device.connect()).await, Some(()));
+    assert_eq!(handle.status().state, DeviceState::Connected);
+}
@@ -0,0 +1,115 @@
+use super::{Device, RawTag, TagInfo, TagSecurity};
+use crate::rfid_items::to_hex_string;
+use crate::routes::write_tags::{WriteError, WriteResponse};
+use std::{collections::HashMap, ops::Range};
+
+// Device which is marked as read-only in the config. Tags are read
+// by the inner device, and writing them is refused
+pub struct ReadOnly(pub Box<dyn Device>);
+
+fn read_only_error() -> WriteError {
+    WriteError {
+        r#type: "Read Only".to_string(),
+        message: "Reader is read-only".to_string(),
+    }
+}
+
+fn refuse(ids: impl Iterator<Item = String>) -> Vec<WriteResponse> {
+    ids.map(|id| WriteResponse {
+        id,
+        success: false,
+        error: Some(read_only_error()),
+        locked: None,
+    })
+    .collect()
+}
+
+impl Device for ReadOnly {
+    fn connect(&mut self) {
+        self.0.connect()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.0.is_connected()
+    }
+
+    fn error(&self) -> Option<String> {
+        self.0.error()
+    }
+
+    fn multi_tag_is_supported(&self) -> bool {
+        self.0.multi_tag_is_supported()
+    }
+
+    fn compound_data_is_supported(&self) -> bool {
+        self.0.compound_data_is_supported()
+    }
+
+    fn security_is_supported(&self) -> bool {
+        self.0.security_is_supported()
+    }
+
+    fn lock_is_supported(&self) -> bool {
+        self.0.lock_is_supported()
+    }
+
+    fn tag_info_is_supported(&self) -> bool {
+        self.0.tag_info_is_supported()
+    }
+
+    fn beep_is_supported(&self) -> bool {
+        self.0.beep_is_supported()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn get_tags(&mut self) -> Vec<RawTag> {
+        self.0.get_tags()
+    }
+
+    fn get_tags_security(&mut self) -> Vec<TagSecurity> {
+        self.0.get_tags_security()
+    }
+
+    fn get_tag_info(&mut self, uid: &[u8]) -> Option<TagInfo> {
+        self.0.get_tag_info(uid)
+    }
+
+    fn set_tags_security(&mut self, params: HashMap<String, bool>) -> Vec<WriteResponse> {
+        refuse(params.into_keys())
+    }
+
+    fn write_tags(&mut self, tags: Vec<RawTag>) -> Vec<WriteResponse> {
+        refuse(tags.iter().map(|tag| to_hex_string(&tag.uid)))
+    }
+
+    fn read_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<Vec<u8>, WriteError> {
+        self.0.read_blocks(uid, blocks)
+    }
+
+    fn write_blocks(&mut self, _: &[u8], _: u8, _: &[u8]) -> Result<(), WriteError> {
+        Err(read_only_error())
+    }
+
+    fn lock_blocks(&mut self, _: &[u8], _: Range<u8>) -> Result<(), WriteError> {
+        Err(read_only_error())
+    }
+
+    // Beeping doesn't change tags
+    fn beep(&mut self) -> Result<(), WriteError> {
+        self.0.beep()
+    }
+}
+
+#[test]
+fn writes_are_refused() {
+    let mut device = ReadOnly(Box::new(super::test_device::TestDevice::default()));
+    assert!(device.is_read_only());
+    assert_eq!(device.get_tags().len(), 1);
+    let responses =
+        device.set_tags_security(HashMap::from([("E004015012345678".to_string(), true)]));
+    assert!(!responses[0].success);
+    assert!(device.write_blocks(&[0; 8], 0, &[0; 4]).is_err());
+}
@@ -0,0 +1,59 @@
+use rocket::serde::{Deserialize, Serialize};
+use serialport::{ClearBuffer, DataBits, FlowControl, StopBits};
+use std::{io, sync::Mutex, time::Duration};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+pub const BAUD_RATES: [u32; 5] = [9600, 19200, 38400, 57600, 115200];
+
+// Serial port with 8 data bits and 1 stop bit.
+// Reads fail with `TimedOut` if nothing is received within `timeout`
+pub struct SerialPort {
+    // Devices are Sync, while ports are only Send. The lock is never contended,
+    // since the port is used through `&mut self`
+    port: Mutex<Box<dyn serialport::SerialPort>>,
+}
+
+impl SerialPort {
+    pub fn open(path: &str, baud_rate: u32, parity: Parity, timeout: Duration) -> io::Result<Self> {
+        let parity = match parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Even => serialport::Parity::Even,
+            Parity::Odd => serialport::Parity::Odd,
+        };
+        let port = serialport::new(path, baud_rate)
+            .data_bits(DataBits::Eight)
+            .stop_bits(StopBits::One)
+            .parity(parity)
+            .flow_control(FlowControl::None)
+            .timeout(timeout)
+            .open()?;
+        Ok(Self {
+            port: Mutex::new(port),
+        })
+    }
+
+    fn port(&mut self) -> &mut dyn serialport::SerialPort {
+        self.port.get_mut().unwrap().as_mut()
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.port().write_all(data)?;
+        self.port().flush()
+    }
+
+    pub fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.port().read_exact(buf)
+    }
+
+    // Drops data which has been received but not read yet
+    pub fn clear(&mut self) {
+        let _ = self.port().clear(ClearBuffer::Input);
+    }
+}
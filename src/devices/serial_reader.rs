@@ -0,0 +1,663 @@
+// Readers connected to a serial port (or a USB-serial adapter) which speak
+// FEIG ISC standard protocol, e.g. Feig ISC.MR101 and ISC.PRH101.
+// ISO 15693 commands are sent as ISO host commands (control byte 0xB0)
+use super::serial_port::{Parity, SerialPort};
+use super::{
+    Device, DeviceOptions, RawTag, TagInfo, TagSecurity, AFI_CHECKED_OUT, AFI_SECURED, BLOCK_SIZE,
+    PRIMARY_BLOCKS,
+};
+use crate::config::SerialPortConfig;
+use crate::rfid_items::{from_hex_string, to_hex_string};
+use crate::routes::write_tags::{WriteError, WriteResponse};
+use log::*;
+use std::{collections::HashMap, fmt, io, ops::Range, time::Duration};
+
+// Readers answer within tens of milliseconds, even when there're no tags
+const TIMEOUT: Duration = Duration::from_millis(500);
+// Frames are up to 255 bytes long, so longer reads and writes are split
+const BLOCKS_PER_FRAME: u8 = 32;
+
+// Control bytes
+const CMD_BAUD_RATE_DETECTION: u8 = 0x52;
+const CMD_ISO_HOST: u8 = 0xB0;
+// ISO host commands, the first data byte after CMD_ISO_HOST
+const ISO_INVENTORY: u8 = 0x01;
+const ISO_LOCK_MULTIPLE_BLOCKS: u8 = 0x22;
+const ISO_READ_MULTIPLE_BLOCKS: u8 = 0x23;
+const ISO_WRITE_MULTIPLE_BLOCKS: u8 = 0x24;
+const ISO_WRITE_AFI: u8 = 0x27;
+const ISO_GET_SYSTEM_INFO: u8 = 0x2B;
+// Mode byte of a command which contains the UID of a tag
+const MODE_ADDRESSED: u8 = 0x01;
+
+// Status bytes of responses
+const STATUS_OK: u8 = 0x00;
+const STATUS_NO_TRANSPONDER: u8 = 0x01;
+// Inventory has found more tags than fit into a response
+const STATUS_MORE_DATA: u8 = 0x94;
+
+pub const DEFAULT_MODEL: &str = "Feig ISC";
+
+#[derive(Debug)]
+enum ReaderError {
+    Io(io::Error),
+    // Wrong length or CRC of a response
+    Frame,
+    Status(u8),
+}
+
+impl fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) if e.kind() == io::ErrorKind::TimedOut => {
+                write!(f, "Reader doesn't answer")
+            }
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Frame => write!(f, "Reader has sent a corrupted response"),
+            Self::Status(STATUS_NO_TRANSPONDER) => write!(f, "There's no tag with this id"),
+            Self::Status(0x02) => write!(f, "Tag has sent corrupted data"),
+            Self::Status(0x03) => write!(f, "Tag memory can't be written"),
+            Self::Status(0x04) => write!(f, "Tag memory doesn't have such blocks"),
+            Self::Status(0x83) => write!(f, "RF communication error"),
+            Self::Status(0x95) => write!(f, "Tag has returned an ISO 15693 error"),
+            Self::Status(s) => write!(f, "Reader has returned status 0x{s:02X}"),
+        }
+    }
+}
+
+// CRC16 of FEIG protocol: polynomial 0x8408, preset 0xFFFF
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for b in data {
+        crc ^= *b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+// Frame: length, bus address, control byte, data, CRC16 (LSB first).
+// Responses have a status byte before the data
+fn frame(address: u8, command: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![(data.len() + 5) as u8, address, command];
+    frame.extend(data);
+    frame.extend(crc16(&frame).to_le_bytes());
+    frame
+}
+
+// UIDs are 8 bytes long, so there can't be a tag with any other id
+fn uid(uid: &[u8]) -> Result<[u8; 8], ReaderError> {
+    uid.try_into()
+        .map_err(|_| ReaderError::Status(STATUS_NO_TRANSPONDER))
+}
+
+pub struct SerialReader {
+    path: String,
+    baud_rate: u32,
+    parity: Parity,
+    // Bus address of the reader, 0xFF is accepted by any reader
+    address: u8,
+    // The protocol has no common commands for LEDs and buzzers,
+    // so only the number of blocks to read is used
+    options: DeviceOptions,
+    port: Option<SerialPort>,
+    error: Option<String>,
+}
+
+impl SerialReader {
+    // The port is opened later on the thread of the device, see connect()
+    pub fn new(config: &SerialPortConfig, options: DeviceOptions) -> Self {
+        Self {
+            path: config.port.clone(),
+            baud_rate: config.baud_rate,
+            parity: config.parity,
+            address: config.address,
+            options,
+            port: None,
+            error: None,
+        }
+    }
+
+    // Returns the status and the data of a response.
+    // The port is closed on I/O errors, so it's reopened by connect()
+    fn transact(&mut self, command: u8, data: &[u8]) -> Result<(u8, Vec<u8>), ReaderError> {
+        let port = self
+            .port
+            .as_mut()
+            .ok_or(ReaderError::Io(io::ErrorKind::NotConnected.into()))?;
+        let result = exchange(port, &frame(self.address, command, data));
+        if let Err(e @ ReaderError::Io(_)) = &result {
+            warn!("Serial port {}: {e}", self.path);
+            self.port = None;
+            self.error = Some(format!("{e} on {}", self.path));
+        }
+        let response = result?;
+        if response[2] != command {
+            return Err(ReaderError::Frame);
+        }
+        Ok((response[3], response[4..response.len() - 2].to_vec()))
+    }
+
+    fn iso_command(&mut self, data: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        match self.transact(CMD_ISO_HOST, data)? {
+            (STATUS_OK, data) => Ok(data),
+            (status, _) => Err(ReaderError::Status(status)),
+        }
+    }
+
+    // DSFIDs and UIDs of tags in the reader field
+    fn inventory(&mut self) -> Result<Vec<(u8, [u8; 8])>, ReaderError> {
+        let data = match self.transact(CMD_ISO_HOST, &[ISO_INVENTORY, 0x00])? {
+            (STATUS_OK | STATUS_MORE_DATA, data) => data,
+            (STATUS_NO_TRANSPONDER, _) => return Ok(vec![]),
+            (status, _) => return Err(ReaderError::Status(status)),
+        };
+        // Data sets: transponder type, DSFID, UID
+        let count = *data.first().ok_or(ReaderError::Frame)? as usize;
+        if data.len() < 1 + count * 10 {
+            return Err(ReaderError::Frame);
+        }
+        Ok(data[1..]
+            .chunks_exact(10)
+            .take(count)
+            .map(|set| (set[1], set[2..10].try_into().unwrap()))
+            .collect())
+    }
+
+    fn read(&mut self, uid: &[u8; 8], blocks: Range<u8>) -> Result<Vec<u8>, ReaderError> {
+        let mut memory = Vec::with_capacity(blocks.len() * BLOCK_SIZE);
+        for first_block in blocks.clone().step_by(BLOCKS_PER_FRAME as usize) {
+            let count = (blocks.end - first_block).min(BLOCKS_PER_FRAME);
+            memory.extend(self.read_frame(uid, first_block, count)?);
+        }
+        Ok(memory)
+    }
+
+    fn read_frame(
+        &mut self,
+        uid: &[u8; 8],
+        first_block: u8,
+        count: u8,
+    ) -> Result<Vec<u8>, ReaderError> {
+        let mut request = vec![ISO_READ_MULTIPLE_BLOCKS, MODE_ADDRESSED];
+        request.extend(uid);
+        request.extend([first_block, count]);
+        let data = self.iso_command(&request)?;
+        // DB-N (number of blocks) and DB-SIZE (block size), then every block (DB)
+        // follows its security status byte (SEC-STATUS), see [0xB0][0x23] Read Multiple
+        // Blocks in "ISO Host Commands" of the FEIG ID ISC.MR101 system manual
+        let [blocks, block_size, ..] = data[..] else {
+            return Err(ReaderError::Frame);
+        };
+        let block_size = block_size as usize;
+        if blocks != count || data.len() != 2 + count as usize * (block_size + 1) {
+            return Err(ReaderError::Frame);
+        }
+        Ok(data[2..]
+            .chunks_exact(block_size + 1)
+            .flat_map(|block| block[1..].to_vec())
+            .collect())
+    }
+
+    fn write(&mut self, uid: &[u8; 8], first_block: u8, data: &[u8]) -> Result<(), ReaderError> {
+        let mut data = data.to_vec();
+        data.resize(data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        for (i, chunk) in data
+            .chunks(BLOCKS_PER_FRAME as usize * BLOCK_SIZE)
+            .enumerate()
+        {
+            let first_block = first_block as usize + i * BLOCKS_PER_FRAME as usize;
+            let first_block = u8::try_from(first_block).map_err(|_| ReaderError::Status(0x04))?;
+            self.write_frame(uid, first_block, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(
+        &mut self,
+        uid: &[u8; 8],
+        first_block: u8,
+        data: &[u8],
+    ) -> Result<(), ReaderError> {
+        let mut request = vec![ISO_WRITE_MULTIPLE_BLOCKS, MODE_ADDRESSED];
+        request.extend(uid);
+        request.extend([
+            first_block,
+            (data.len() / BLOCK_SIZE) as u8,
+            BLOCK_SIZE as u8,
+        ]);
+        request.extend(data);
+        self.iso_command(&request).map(|_| ())
+    }
+
+    fn system_info(&mut self, uid: &[u8; 8]) -> Result<TagInfo, ReaderError> {
+        let mut request = vec![ISO_GET_SYSTEM_INFO, MODE_ADDRESSED];
+        request.extend(uid);
+        let data = self.iso_command(&request)?;
+        // DSFID, UID, AFI, memory size (block size - 1, block count - 1), IC reference
+        if data.len() < 13 {
+            return Err(ReaderError::Frame);
+        }
+        Ok(TagInfo {
+            uid: data[1..9].to_vec(),
+            dsfid: Some(data[0]),
+            afi: Some(data[9]),
+            block_size: Some((data[10] & 0x1F) + 1),
+            block_count: Some(data[11] as u16 + 1),
+            ic_reference: Some(data[12]),
+        })
+    }
+}
+
+fn exchange(port: &mut SerialPort, request: &[u8]) -> Result<Vec<u8>, ReaderError> {
+    // A late response to a previous request mustn't be taken for this one
+    port.clear();
+    port.write_all(request).map_err(ReaderError::Io)?;
+    let mut len = [0u8];
+    port.read_exact(&mut len).map_err(ReaderError::Io)?;
+    let len = len[0] as usize;
+    if len < 6 {
+        return Err(ReaderError::Frame);
+    }
+    let mut response = vec![0u8; len];
+    response[0] = len as u8;
+    port.read_exact(&mut response[1..])
+        .map_err(ReaderError::Io)?;
+    let crc = u16::from_le_bytes([response[len - 2], response[len - 1]]);
+    if crc16(&response[..len - 2]) != crc {
+        return Err(ReaderError::Frame);
+    }
+    Ok(response)
+}
+
+impl Device for SerialReader {
+    fn connect(&mut self) {
+        if self.port.is_some() && self.transact(CMD_BAUD_RATE_DETECTION, &[0x00]).is_ok() {
+            return;
+        }
+        self.port = match SerialPort::open(&self.path, self.baud_rate, self.parity, TIMEOUT) {
+            Ok(port) => Some(port),
+            Err(e) => {
+                // The port of a USB-serial adapter disappears when it's unplugged
+                self.error = (e.kind() != io::ErrorKind::NotFound).then(|| e.to_string());
+                return;
+            }
+        };
+        match self.transact(CMD_BAUD_RATE_DETECTION, &[0x00]) {
+            Ok(_) => self.error = None,
+            // The port is closed by transact()
+            Err(ReaderError::Io(_)) => (),
+            // Probably it's another device or a wrong baud rate
+            Err(e) => {
+                self.port = None;
+                self.error = Some(format!("{e} on {}", self.path));
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.port.is_some()
+    }
+
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    fn multi_tag_is_supported(&self) -> bool {
+        true
+    }
+
+    fn compound_data_is_supported(&self) -> bool {
+        true
+    }
+
+    fn security_is_supported(&self) -> bool {
+        true
+    }
+
+    fn lock_is_supported(&self) -> bool {
+        true
+    }
+
+    fn tag_info_is_supported(&self) -> bool {
+        true
+    }
+
+    fn beep_is_supported(&self) -> bool {
+        false
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn get_tags(&mut self) -> Vec<RawTag> {
+        let inventory = self.inventory().unwrap_or_default();
+        let mut tags = Vec::with_capacity(inventory.len());
+        for (dsfid, uid) in inventory {
+            // Tags with a smaller memory return an error, so only the primary block is read then
+            let memory = self
+                .read(&uid, 0..self.options.read_blocks)
+                .or_else(|_| self.read(&uid, 0..PRIMARY_BLOCKS));
+            match memory {
+                Ok(memory) => tags.push(RawTag {
+                    uid: uid.to_vec(),
+                    dsfid: Some(dsfid),
+                    memory,
+                }),
+                Err(e) => warn!("Unable to read tag {}: {e}", to_hex_string(&uid)),
+            }
+        }
+        tags
+    }
+
+    fn get_tags_security(&mut self) -> Vec<TagSecurity> {
+        let inventory = self.inventory().unwrap_or_default();
+        inventory
+            .into_iter()
+            .map(|(_, uid)| TagSecurity {
+                tag_id: to_hex_string(&uid),
+                afi: self.system_info(&uid).ok().and_then(|info| info.afi),
+            })
+            .collect()
+    }
+
+    fn get_tag_info(&mut self, uid: &[u8]) -> Option<TagInfo> {
+        let uid = self::uid(uid).ok()?;
+        self.system_info(&uid).ok()
+    }
+
+    fn set_tags_security(&mut self, params: HashMap<String, bool>) -> Vec<WriteResponse> {
+        params
+            .into_iter()
+            .map(|(id, is_secured)| {
+                let afi = if is_secured {
+                    AFI_SECURED
+                } else {
+                    AFI_CHECKED_OUT
+                };
+                let result = from_hex_string(&id)
+                    .map_err(|_| ReaderError::Status(STATUS_NO_TRANSPONDER))
+                    .and_then(|uid| self::uid(&uid))
+                    .and_then(|uid| {
+                        let mut request = vec![ISO_WRITE_AFI, MODE_ADDRESSED];
+                        request.extend(uid);
+                        request.push(afi);
+                        self.iso_command(&request)
+                    });
+                match result {
+                    Ok(_) => WriteResponse {
+                        id,
+                        success: true,
+                        error: None,
+                        locked: None,
+                    },
+                    Err(e) => WriteResponse {
+                        id,
+                        success: false,
+                        error: Some(WriteError {
+                            r#type: "Security Error".to_string(),
+                            message: e.to_string(),
+                        }),
+                        locked: None,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn write_tags(&mut self, tags: Vec<RawTag>) -> Vec<WriteResponse> {
+        if self.port.is_none() {
+            return tags
+                .iter()
+                .map(|tag| write_error(&tag.uid, "Couldn't connect to the reader"))
+                .collect();
+        }
+        let inventory = self.inventory().unwrap_or_default();
+        let mut responses = Vec::with_capacity(tags.len());
+        for tag in &tags {
+            // A tag without UID can be written only if it's the only one
+            let target = if tag.uid.is_empty() && tags.len() == 1 && inventory.len() == 1 {
+                Some(inventory[0].1)
+            } else {
+                inventory
+                    .iter()
+                    .find(|(_, uid)| uid[..] == tag.uid[..])
+                    .map(|(_, uid)| *uid)
+            };
+            let Some(uid) = target else {
+                responses.push(write_error(
+                    &tag.uid,
+                    "Error during writing a card. Probably there's no cards nearby.",
+                ));
+                continue;
+            };
+            match self.write(&uid, 0, &tag.memory) {
+                Ok(()) => responses.push(WriteResponse {
+                    id: to_hex_string(&uid),
+                    success: true,
+                    error: None,
+                    locked: None,
+                }),
+                Err(e) => {
+                    warn!("Unable to write tag {}: {e}", to_hex_string(&uid));
+                    responses.push(write_error(&uid, "Error during writing a card"));
+                }
+            }
+        }
+        responses
+    }
+
+    fn read_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<Vec<u8>, WriteError> {
+        let uid = self::uid(uid).map_err(raw_error)?;
+        self.read(&uid, blocks).map_err(raw_error)
+    }
+
+    fn write_blocks(&mut self, uid: &[u8], first_block: u8, data: &[u8]) -> Result<(), WriteError> {
+        let uid = self::uid(uid).map_err(raw_error)?;
+        self.write(&uid, first_block, data).map_err(raw_error)
+    }
+
+    fn lock_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<(), WriteError> {
+        let uid = self::uid(uid).map_err(raw_error)?;
+        let mut request = vec![ISO_LOCK_MULTIPLE_BLOCKS, MODE_ADDRESSED];
+        request.extend(uid);
+        request.extend([blocks.start, blocks.len() as u8]);
+        self.iso_command(&request).map(|_| ()).map_err(raw_error)
+    }
+
+    fn beep(&mut self) -> Result<(), WriteError> {
+        Err(WriteError {
+            r#type: "Reader Error".to_string(),
+            message: "Reader doesn't support the buzzer".to_string(),
+        })
+    }
+}
+
+fn raw_error(e: ReaderError) -> WriteError {
+    WriteError {
+        r#type: "Reader Error".to_string(),
+        message: e.to_string(),
+    }
+}
+
+fn write_error(uid: &[u8], message: &str) -> WriteResponse {
+    WriteResponse {
+        id: to_hex_string(uid),
+        success: false,
+        error: Some(WriteError {
+            r#type: "Write Error".to_string(),
+            message: message.to_string(),
+        }),
+        locked: None,
+    }
+}
+
+#[test]
+fn frame_crc() {
+    // Check value of CRC-16/MCRF4XX, which is the same algorithm
+    assert_eq!(crc16(b"123456789"), 0x6F91);
+    let frame = frame(0xFF, CMD_BAUD_RATE_DETECTION, &[0x00]);
+    assert_eq!(frame[..4], [0x06, 0xFF, 0x52, 0x00]);
+    assert_eq!(crc16(&frame[..4]).to_le_bytes(), frame[4..]);
+}
+
+// Stand-in reader on the master side of a pseudo-terminal.
+// Tags are (UID, AFI, memory), blocks are 4 bytes long
+#[cfg(all(test, unix))]
+fn emulate_reader(mut master: serialport::TTYPort, mut tags: Vec<([u8; 8], u8, Vec<u8>)>) {
+    use std::io::{Read, Write};
+    // UIDs of tags and their locked blocks
+    let mut locked: Vec<([u8; 8], usize)> = vec![];
+    loop {
+        let mut len = [0u8];
+        if master.read_exact(&mut len).is_err() {
+            return;
+        }
+        let mut request = vec![0u8; len[0] as usize];
+        request[0] = len[0];
+        if master.read_exact(&mut request[1..]).is_err() {
+            return;
+        }
+        let (command, data) = (request[2], &request[3..request.len() - 2]);
+        let tag = (data.len() >= 10)
+            .then(|| tags.iter_mut().find(|t| t.0[..] == data[2..10]))
+            .flatten();
+        let (status, response): (u8, Vec<u8>) = match (command, data.first(), tag) {
+            (CMD_BAUD_RATE_DETECTION, _, _) => (STATUS_OK, vec![]),
+            (_, Some(&ISO_INVENTORY), _) => {
+                let mut response = vec![tags.len() as u8];
+                for (uid, _, _) in &tags {
+                    response.extend([0x03, 0x00]);
+                    response.extend(uid);
+                }
+                (STATUS_OK, response)
+            }
+            (_, _, None) => (STATUS_NO_TRANSPONDER, vec![]),
+            (_, Some(&ISO_READ_MULTIPLE_BLOCKS), Some((uid, _, memory))) => {
+                let (first, count) = (data[10] as usize, data[11] as usize);
+                match memory.get(first * 4..(first + count) * 4) {
+                    Some(blocks) => {
+                        let mut response = vec![count as u8, 4];
+                        for (i, block) in blocks.chunks(4).enumerate() {
+                            response.push(locked.contains(&(*uid, first + i)) as u8);
+                            response.extend(block);
+                        }
+                        (STATUS_OK, response)
+                    }
+                    None => (0x04, vec![]),
+                }
+            }
+            (_, Some(&ISO_WRITE_MULTIPLE_BLOCKS), Some((uid, _, memory))) => {
+                let (first, count) = (data[10] as usize, data[11] as usize);
+                let blocks = &data[13..];
+                if (first..first + count).any(|block| locked.contains(&(*uid, block))) {
+                    // ISO 15693 error 0x12: the block is locked
+                    (0x95, vec![0x12])
+                } else {
+                    memory[first * 4..first * 4 + blocks.len()].copy_from_slice(blocks);
+                    (STATUS_OK, vec![])
+                }
+            }
+            (_, Some(&ISO_WRITE_AFI), Some((_, afi, _))) => {
+                *afi = data[10];
+                (STATUS_OK, vec![])
+            }
+            (_, Some(&ISO_GET_SYSTEM_INFO), Some((uid, afi, memory))) => {
+                let mut response = vec![0x00];
+                response.extend(*uid);
+                response.extend([*afi, 0x03, (memory.len() / 4 - 1) as u8, 0x01]);
+                (STATUS_OK, response)
+            }
+            (_, Some(&ISO_LOCK_MULTIPLE_BLOCKS), Some((uid, _, _))) => {
+                let (first, count) = (data[10] as usize, data[11] as usize);
+                locked.extend((first..first + count).map(|block| (*uid, block)));
+                (STATUS_OK, vec![])
+            }
+            _ => (0x80, vec![]),
+        };
+        let mut data = vec![status];
+        data.extend(response);
+        if master.write_all(&frame(0x00, command, &data)).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn pty_reader() {
+    let first = [0xE0, 0x04, 0x01, 0x50, 0x00, 0x00, 0x00, 0x01];
+    let second = [0xE0, 0x04, 0x01, 0x50, 0x00, 0x00, 0x00, 0x02];
+    // The slave side is kept open, so reads of the master side don't fail
+    use serialport::SerialPort as _;
+    let (mut master, slave) = serialport::TTYPort::pair().unwrap();
+    let path = slave.name().unwrap();
+    master.set_timeout(Duration::from_secs(60)).unwrap();
+    let tags = vec![
+        (first, AFI_SECURED, vec![0x11; 28 * 4]),
+        // Smaller memory than the default number of blocks to read
+        (second, AFI_CHECKED_OUT, vec![0x22; 8 * 4]),
+    ];
+    std::thread::spawn(move || emulate_reader(master, tags));
+
+    let config = SerialPortConfig {
+        port: path,
+        ..Default::default()
+    };
+    let mut reader = SerialReader::new(&config, DeviceOptions::from(&Default::default()));
+    reader.connect();
+    assert!(reader.is_connected());
+    assert_eq!(reader.error(), None);
+
+    let tags = reader.get_tags();
+    assert_eq!(tags.len(), 2);
+    assert_eq!(tags[0].uid, first);
+    assert_eq!(tags[0].memory, vec![0x11; 28 * 4]);
+    assert_eq!(tags[1].memory, vec![0x22; 8 * 4]);
+
+    let memory = vec![0x33; 6];
+    let written = reader.write_tags(vec![RawTag {
+        uid: second.to_vec(),
+        dsfid: None,
+        memory,
+    }]);
+    assert!(written[0].success);
+    assert_eq!(
+        reader.read_blocks(&second, 0..3).unwrap(),
+        [&[0x33; 6][..], &[0, 0], &[0x22; 4]].concat()
+    );
+
+    let info = reader.get_tag_info(&second).unwrap();
+    assert_eq!(info.afi, Some(AFI_CHECKED_OUT));
+    assert_eq!((info.block_size, info.block_count), (Some(4), Some(8)));
+    assert!(reader.get_tag_info(&[0; 8]).is_none());
+
+    let secured = reader.set_tags_security(HashMap::from([(to_hex_string(&second), true)]));
+    assert!(secured[0].success);
+    let security = reader.get_tags_security();
+    assert!(security.iter().all(|tag| tag.afi == Some(AFI_SECURED)));
+
+    assert!(reader.lock_blocks(&first, 0..2).is_ok());
+    assert!(reader.write_blocks(&first, 1, &[0x44; 8]).is_err());
+    assert_eq!(reader.read_blocks(&first, 0..3).unwrap(), vec![0x11; 12]);
+    assert!(reader.write_blocks(&first, 2, &[0x44; 4]).is_ok());
+}
+
+#[test]
+fn missing_port() {
+    let config = SerialPortConfig {
+        port: "/dev/nonexistent-serial-port".to_string(),
+        ..Default::default()
+    };
+    let mut reader = SerialReader::new(&config, DeviceOptions::from(&Default::default()));
+    reader.connect();
+    assert!(!reader.is_connected());
+    // It's just unplugged
+    assert_eq!(reader.error(), None);
+}
@@ -0,0 +1,472 @@
+// Virtual reader for development without hardware. Tags are loaded from a scenario file:
+// {"tags": [{"uid": "E004015012345678", "memory": "<hex>"}], "faults": ["writeFailure"]}
+// The file is read again when it's changed, so faults can be switched on the fly.
+// Writes change only the tags in memory, and they're kept until the tags in the file are edited
+use super::{
+    Device, DeviceOptions, RawTag, TagInfo, TagSecurity, AFI_CHECKED_OUT, AFI_SECURED, BLOCK_SIZE,
+    PRIMARY_BLOCKS,
+};
+use crate::rfid_items::{danish::DanishRfidItem, from_hex_string, to_hex_string, TagDataModel};
+use crate::routes::write_tags::{WriteError, WriteResponse};
+use log::*;
+use rocket::serde::{json, Deserialize};
+use std::{collections::HashMap, fs, ops::Range, time::SystemTime};
+
+pub const MODEL: &str = "Simulator";
+// Low byte of the CRC in the primary block of the Danish data model
+const DANISH_CRC_BYTE: usize = 19;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+enum Fault {
+    // The reader field is empty
+    NoTag,
+    // Tags are read with a wrong CRC of the Danish data model
+    CrcError,
+    // Tags can't be written, secured or locked
+    WriteFailure,
+    // Writing succeeds, but the last written byte is inverted
+    CorruptWrite,
+    // Tags can be written, but not locked
+    LockFailure,
+    // The reader is unplugged
+    Disconnect,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct Scenario {
+    tags: Vec<ScenarioTag>,
+    faults: Vec<Fault>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "rocket::serde", default)]
+struct ScenarioTag {
+    uid: String,
+    // Hex, the rest of the memory is zeroed
+    memory: String,
+    dsfid: u8,
+    afi: u8,
+    block_count: u8,
+}
+
+impl Default for ScenarioTag {
+    fn default() -> Self {
+        Self {
+            uid: String::new(),
+            memory: String::new(),
+            dsfid: 0,
+            afi: AFI_SECURED,
+            block_count: 28,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VirtualTag {
+    uid: Vec<u8>,
+    dsfid: u8,
+    afi: u8,
+    memory: Vec<u8>,
+    locked: Vec<bool>, // By blocks
+}
+
+impl TryFrom<&ScenarioTag> for VirtualTag {
+    type Error = String;
+
+    fn try_from(tag: &ScenarioTag) -> Result<Self, String> {
+        let uid = from_hex_string(&tag.uid)
+            .ok()
+            .filter(|uid| uid.len() == 8)
+            .ok_or(format!("uid {} must be 8 bytes in hex", tag.uid))?;
+        let hex: String = tag.memory.split_whitespace().collect();
+        let mut memory =
+            from_hex_string(&hex).map_err(|_| format!("memory of tag {} isn't hex", tag.uid))?;
+        if tag.block_count < PRIMARY_BLOCKS {
+            return Err(format!(
+                "tag {} must have at least {PRIMARY_BLOCKS} blocks",
+                tag.uid
+            ));
+        }
+        let size = tag.block_count as usize * BLOCK_SIZE;
+        if memory.len() > size {
+            return Err(format!(
+                "memory of tag {} is longer than {} blocks",
+                tag.uid, tag.block_count
+            ));
+        }
+        memory.resize(size, 0);
+        Ok(Self {
+            uid,
+            dsfid: tag.dsfid,
+            afi: tag.afi,
+            memory,
+            locked: vec![false; tag.block_count as usize],
+        })
+    }
+}
+
+// A library item which is used when there's no scenario file
+fn default_tag() -> VirtualTag {
+    let mut item = DanishRfidItem::default();
+    let _ = item.set_item_id("1234567890");
+    let _ = item.set_country("RU");
+    let _ = item.set_library_id("123");
+    let mut tag = VirtualTag::try_from(&ScenarioTag {
+        uid: "E004015012345678".to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    let bytes = item.to_bytes();
+    tag.memory[..bytes.len()].copy_from_slice(&bytes);
+    tag
+}
+
+pub struct Simulator {
+    scenario: Option<String>,
+    options: DeviceOptions,
+    // Modification time and size of the loaded file
+    loaded: Option<(SystemTime, u64)>,
+    // Tags as they're in the file, to find out whether they're edited
+    scenario_tags: Vec<ScenarioTag>,
+    tags: Vec<VirtualTag>,
+    faults: Vec<Fault>,
+    error: Option<String>,
+}
+
+impl Simulator {
+    // The scenario is loaded by connect()
+    pub fn new(scenario: Option<String>, options: DeviceOptions) -> Self {
+        Self {
+            tags: match scenario {
+                Some(_) => vec![],
+                None => vec![default_tag()],
+            },
+            scenario,
+            options,
+            loaded: None,
+            scenario_tags: vec![],
+            faults: vec![],
+            error: None,
+        }
+    }
+
+    fn reload(&mut self) {
+        let Some(path) = &self.scenario else {
+            return;
+        };
+        let file = fs::metadata(path)
+            .and_then(|m| Ok((m.modified()?, m.len())))
+            .ok();
+        if file.is_some() && file == self.loaded {
+            return;
+        }
+        self.loaded = file;
+
+        let scenario = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| json::from_str::<Scenario>(&s).map_err(|e| e.to_string()))
+            .and_then(|scenario| {
+                let tags = scenario
+                    .tags
+                    .iter()
+                    .map(VirtualTag::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((scenario, tags))
+            });
+        match scenario {
+            Ok((scenario, tags)) => {
+                info!("Scenario {path} is loaded");
+                if scenario.tags != self.scenario_tags {
+                    self.scenario_tags = scenario.tags;
+                    self.tags = tags;
+                }
+                self.faults = scenario.faults;
+                self.error = None;
+            }
+            // The previous scenario is kept
+            Err(e) => self.error = Some(format!("Scenario {path}: {e}")),
+        }
+    }
+
+    fn has_fault(&self, fault: Fault) -> bool {
+        self.faults.contains(&fault)
+    }
+
+    // Tags in the reader field
+    fn field(&mut self) -> &mut [VirtualTag] {
+        if !self.is_connected() || self.has_fault(Fault::NoTag) {
+            return &mut [];
+        }
+        &mut self.tags
+    }
+
+    fn tag(&mut self, uid: &[u8]) -> Result<&mut VirtualTag, WriteError> {
+        self.field()
+            .iter_mut()
+            .find(|tag| tag.uid == uid)
+            .ok_or(simulator_error("There's no tag with this id"))
+    }
+
+    fn check_writing(&self) -> Result<(), WriteError> {
+        match self.has_fault(Fault::WriteFailure) {
+            true => Err(simulator_error("Simulated write failure")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Device for Simulator {
+    fn connect(&mut self) {
+        self.reload();
+    }
+
+    fn is_connected(&self) -> bool {
+        self.error.is_none() && !self.has_fault(Fault::Disconnect)
+    }
+
+    fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    fn multi_tag_is_supported(&self) -> bool {
+        true
+    }
+
+    fn compound_data_is_supported(&self) -> bool {
+        true
+    }
+
+    fn security_is_supported(&self) -> bool {
+        true
+    }
+
+    fn lock_is_supported(&self) -> bool {
+        true
+    }
+
+    fn tag_info_is_supported(&self) -> bool {
+        true
+    }
+
+    fn beep_is_supported(&self) -> bool {
+        true
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn get_tags(&mut self) -> Vec<RawTag> {
+        let read_blocks = self.options.read_blocks;
+        let crc_error = self.has_fault(Fault::CrcError);
+        self.field()
+            .iter()
+            .map(|tag| {
+                // Like real readers, only the primary block is read from smaller tags
+                let blocks = match tag.locked.len() < read_blocks as usize {
+                    true => PRIMARY_BLOCKS,
+                    false => read_blocks,
+                };
+                let mut memory = tag.memory[..blocks as usize * BLOCK_SIZE].to_vec();
+                if crc_error {
+                    memory[DANISH_CRC_BYTE] ^= 0xFF;
+                }
+                RawTag {
+                    uid: tag.uid.clone(),
+                    dsfid: Some(tag.dsfid),
+                    memory,
+                }
+            })
+            .collect()
+    }
+
+    fn get_tags_security(&mut self) -> Vec<TagSecurity> {
+        self.field()
+            .iter()
+            .map(|tag| TagSecurity {
+                tag_id: to_hex_string(&tag.uid),
+                afi: Some(tag.afi),
+            })
+            .collect()
+    }
+
+    fn get_tag_info(&mut self, uid: &[u8]) -> Option<TagInfo> {
+        let tag = self.tag(uid).ok()?;
+        Some(TagInfo {
+            uid: tag.uid.clone(),
+            dsfid: Some(tag.dsfid),
+            afi: Some(tag.afi),
+            block_size: Some(BLOCK_SIZE as u8),
+            block_count: Some(tag.locked.len() as u16),
+            ic_reference: Some(0x01),
+        })
+    }
+
+    fn set_tags_security(&mut self, params: HashMap<String, bool>) -> Vec<WriteResponse> {
+        params
+            .into_iter()
+            .map(|(id, is_secured)| {
+                let uid = from_hex_string(&id).unwrap_or_default();
+                let result = self.check_writing().and_then(|_| {
+                    self.tag(&uid)?.afi = match is_secured {
+                        true => AFI_SECURED,
+                        false => AFI_CHECKED_OUT,
+                    };
+                    Ok(())
+                });
+                WriteResponse {
+                    id,
+                    success: result.is_ok(),
+                    error: result.err(),
+                    locked: None,
+                }
+            })
+            .collect()
+    }
+
+    fn write_tags(&mut self, tags: Vec<RawTag>) -> Vec<WriteResponse> {
+        let field: Vec<Vec<u8>> = self.field().iter().map(|tag| tag.uid.clone()).collect();
+        let count = tags.len();
+        tags.into_iter()
+            .map(|tag| {
+                // A tag without UID can be written only if it's the only one
+                let uid = match tag.uid.is_empty() && count == 1 && field.len() == 1 {
+                    true => field[0].clone(),
+                    false => tag.uid,
+                };
+                let result = self.write_blocks(&uid, 0, &tag.memory);
+                WriteResponse {
+                    id: to_hex_string(&uid),
+                    success: result.is_ok(),
+                    error: result.err(),
+                    locked: None,
+                }
+            })
+            .collect()
+    }
+
+    fn read_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<Vec<u8>, WriteError> {
+        let tag = self.tag(uid)?;
+        let bytes = blocks.start as usize * BLOCK_SIZE..blocks.end as usize * BLOCK_SIZE;
+        tag.memory
+            .get(bytes)
+            .map(|data| data.to_vec())
+            .ok_or(simulator_error("Tag memory doesn't have such blocks"))
+    }
+
+    fn write_blocks(&mut self, uid: &[u8], first_block: u8, data: &[u8]) -> Result<(), WriteError> {
+        self.check_writing()?;
+        let corrupt_write = self.has_fault(Fault::CorruptWrite);
+        let tag = self.tag(uid)?;
+        let blocks = first_block as usize..first_block as usize + data.len().div_ceil(BLOCK_SIZE);
+        match tag.locked.get(blocks) {
+            None => Err(simulator_error("Tag memory doesn't have such blocks")),
+            Some(locked) if locked.contains(&true) => {
+                Err(simulator_error("Blocks of the tag are locked"))
+            }
+            Some(_) => {
+                let start = first_block as usize * BLOCK_SIZE;
+                tag.memory[start..start + data.len()].copy_from_slice(data);
+                if corrupt_write && !data.is_empty() {
+                    tag.memory[start + data.len() - 1] ^= 0xFF;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn lock_blocks(&mut self, uid: &[u8], blocks: Range<u8>) -> Result<(), WriteError> {
+        self.check_writing()?;
+        if self.has_fault(Fault::LockFailure) {
+            return Err(simulator_error("Simulated lock failure"));
+        }
+        let tag = self.tag(uid)?;
+        match tag
+            .locked
+            .get_mut(blocks.start as usize..blocks.end as usize)
+        {
+            Some(locked) => {
+                locked.fill(true);
+                Ok(())
+            }
+            None => Err(simulator_error("Tag memory doesn't have such blocks")),
+        }
+    }
+
+    fn beep(&mut self) -> Result<(), WriteError> {
+        if !self.is_connected() {
+            return Err(simulator_error("Simulator is disconnected"));
+        }
+        info!("Simulator beeps");
+        Ok(())
+    }
+}
+
+fn simulator_error(message: &str) -> WriteError {
+    WriteError {
+        r#type: "Simulator Error".to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[test]
+fn scenario() {
+    use crate::rfid_items::{decode, TagStatus};
+
+    let path = std::env::temp_dir().join(format!("rfid_sfl_scenario_{}.json", std::process::id()));
+    let write_scenario = |faults: &str| {
+        let item = to_hex_string(&default_tag().memory[..32]);
+        let scenario = format!(
+            r#"{{"tags": [
+                {{"uid": "E004015000000001", "memory": "{item}"}},
+                {{"uid": "E004015000000002", "block_count": 8}}
+            ], "faults": [{faults}]}}"#
+        );
+        fs::write(&path, scenario).unwrap();
+    };
+    write_scenario("");
+
+    let mut simulator = Simulator::new(
+        Some(path.to_string_lossy().into_owned()),
+        DeviceOptions::from(&Default::default()),
+    );
+    simulator.connect();
+    assert!(simulator.is_connected());
+    let tags = simulator.get_tags();
+    assert_eq!(tags.len(), 2);
+    assert_eq!(tags[0].memory.len(), 28 * BLOCK_SIZE);
+    // A smaller tag is read up to the end of the primary block
+    assert_eq!(tags[1].memory.len(), 8 * BLOCK_SIZE);
+
+    // Writes are kept when only faults are changed
+    let blank = [0xE0, 0x04, 0x01, 0x50, 0x00, 0x00, 0x00, 0x02];
+    let written = simulator.write_tags(vec![RawTag {
+        uid: blank.to_vec(),
+        dsfid: None,
+        memory: tags[0].memory[..32].to_vec(),
+    }]);
+    assert!(written[0].success);
+    write_scenario(r#""crcError", "writeFailure""#);
+    simulator.connect();
+    let tags = simulator.get_tags();
+    assert_eq!(tags[1].memory, tags[0].memory[..32]);
+    assert_eq!(decode(None, &tags[1].memory).status(), TagStatus::CrcError);
+    assert!(simulator.write_blocks(&blank, 0, &[0; 4]).is_err());
+
+    write_scenario(r#""noTag""#);
+    simulator.connect();
+    assert!(simulator.is_connected());
+    assert!(simulator.get_tags().is_empty());
+
+    write_scenario(r#""disconnect""#);
+    simulator.connect();
+    assert!(!simulator.is_connected());
+    assert_eq!(simulator.error(), None);
+
+    fs::write(&path, "{\"tags\": [{\"uid\": \"E0\"}]}").unwrap();
+    simulator.connect();
+    assert!(simulator.error().is_some());
+    fs::remove_file(&path).unwrap();
+}
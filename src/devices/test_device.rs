@@ -1,5 +1,14 @@
-use crate::{devices::Device, rfid_items::DanishRfidItem, routes::write_tags::WriteResponse};
-pub struct TestDevice;
+use crate::{
+    devices::{Device, RawTag, TagInfo, TagSecurity, AFI_SECURED},
+    rfid_items::{danish::DanishRfidItem, to_hex_string, TagDataModel},
+    routes::write_tags::{WriteError, WriteResponse},
+};
+use std::{collections::HashMap, ops::Range};
+// A reader with every feature, or without the optional ones if `is_basic` is set
+#[derive(Default)]
+pub struct TestDevice {
+    pub is_basic: bool,
+}
 
 impl Device for TestDevice {
     fn connect(&mut self) {}
@@ -12,6 +21,22 @@ impl Device for TestDevice {
         true
     }
 
+    fn security_is_supported(&self) -> bool {
+        !self.is_basic
+    }
+
+    fn lock_is_supported(&self) -> bool {
+        !self.is_basic
+    }
+
+    fn tag_info_is_supported(&self) -> bool {
+        !self.is_basic
+    }
+
+    fn beep_is_supported(&self) -> bool {
+        !self.is_basic
+    }
+
     fn is_read_only(&self) -> bool {
         false
     }
@@ -20,7 +45,7 @@ impl Device for TestDevice {
         true
     }
 
-    fn get_items(&mut self) -> Vec<DanishRfidItem> {
+    fn get_tags(&mut self) -> Vec<RawTag> {
         let mut item = DanishRfidItem::default();
         if item.set_item_id("1234567890").is_err() {
             return vec![];
@@ -32,10 +57,70 @@ impl Device for TestDevice {
             return vec![];
         }
 
-        vec![item]
+        vec![RawTag {
+            uid: vec![0xE0, 0x04, 0x01, 0x50, 0x12, 0x34, 0x56, 0x78],
+            dsfid: None,
+            memory: item.to_bytes(),
+        }]
+    }
+
+    fn get_tags_security(&mut self) -> Vec<TagSecurity> {
+        vec![TagSecurity {
+            tag_id: "E004015012345678".to_string(),
+            afi: Some(AFI_SECURED),
+        }]
+    }
+
+    fn get_tag_info(&mut self, uid: &[u8]) -> Option<TagInfo> {
+        if uid != [0xE0, 0x04, 0x01, 0x50, 0x12, 0x34, 0x56, 0x78] {
+            return None;
+        }
+        Some(TagInfo {
+            uid: uid.to_vec(),
+            dsfid: Some(0x00),
+            afi: Some(AFI_SECURED),
+            block_size: Some(4),
+            block_count: Some(28),
+            ic_reference: Some(0x01),
+        })
+    }
+
+    fn set_tags_security(&mut self, params: HashMap<String, bool>) -> Vec<WriteResponse> {
+        params
+            .into_keys()
+            .map(|id| WriteResponse {
+                id,
+                success: true,
+                error: None,
+                locked: None,
+            })
+            .collect()
+    }
+
+    fn write_tags(&mut self, tags: Vec<RawTag>) -> Vec<WriteResponse> {
+        tags.iter()
+            .map(|tag| WriteResponse {
+                id: to_hex_string(&tag.uid),
+                success: true,
+                error: None,
+                locked: None,
+            })
+            .collect()
+    }
+
+    fn read_blocks(&mut self, _: &[u8], blocks: Range<u8>) -> Result<Vec<u8>, WriteError> {
+        Ok(vec![0; blocks.len() * 4])
+    }
+
+    fn write_blocks(&mut self, _: &[u8], _: u8, _: &[u8]) -> Result<(), WriteError> {
+        Ok(())
+    }
+
+    fn lock_blocks(&mut self, _: &[u8], _: Range<u8>) -> Result<(), WriteError> {
+        Ok(())
     }
 
-    fn write_tags(&mut self, _: Vec<DanishRfidItem>) -> Vec<WriteResponse> {
-        vec![]
+    fn beep(&mut self) -> Result<(), WriteError> {
+        Ok(())
     }
 }
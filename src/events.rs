@@ -0,0 +1,253 @@
+use crate::devices::{DeviceHandle, DevicesList, RawTag};
+use crate::rfid_items::to_hex_string;
+use crate::routes::get_items_list::{tag_response, ItemResponse};
+use log::*;
+use rocket::tokio::runtime;
+use rocket::tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, RecvTimeoutError},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// Number of events a slow subscriber may fall behind before it starts losing them
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagEventKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl TagEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Added => "tagAdded",
+            Self::Removed => "tagRemoved",
+            Self::Changed => "tagChanged",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TagEvent {
+    pub kind: TagEventKind,
+    pub item: ItemResponse,
+}
+
+impl TagEvent {
+    fn new(kind: TagEventKind, tag: &RawTag) -> Self {
+        Self {
+            kind,
+            item: ItemResponse::from(tag_response(tag, false)),
+        }
+    }
+}
+
+// Events of a single device and the tags which are in the reader field now
+struct Channel {
+    sender: Sender<TagEvent>,
+    known: Arc<Mutex<Vec<RawTag>>>,
+    // The poller stops when it's dropped
+    stop: mpsc::Sender<()>,
+    poller: JoinHandle<()>,
+}
+
+impl Channel {
+    fn start(id: &str, device: Arc<DeviceHandle>, poll_interval: Duration) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let known = Arc::new(Mutex::new(Vec::new()));
+        let (stop, stopped) = mpsc::channel();
+
+        let thread_sender = sender.clone();
+        let thread_known = known.clone();
+        let thread_id = id.to_string();
+        // Replies of the device are awaited on the poller's own thread
+        let runtime = runtime::Builder::new_current_thread().build().unwrap();
+        let poller = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(poll_interval) {
+                // A recent enough read made for other requests is used as well
+                let inventory = match runtime.block_on(device.inventory(poll_interval)) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                let mut known = thread_known.lock().unwrap();
+                for event in diff(&known, &inventory.tags) {
+                    debug!("{}: {} {:?}", thread_id, event.kind.as_str(), event.item);
+                    // Sending fails only if all subscribers have just gone
+                    let _ = thread_sender.send(event);
+                }
+                *known = inventory.tags.clone();
+            }
+            debug!("{thread_id}: events aren't polled anymore");
+        });
+        Self {
+            sender,
+            known,
+            stop,
+            poller,
+        }
+    }
+
+    // Waits for the current poll of the device, if there's one
+    fn stop(self) {
+        drop(self.stop);
+        if self.poller.join().is_err() {
+            error!("Poller of events has panicked");
+        }
+    }
+}
+
+type Channels = Arc<Mutex<HashMap<String, Channel>>>;
+
+// Polls devices in the background and broadcasts changes of the reader field.
+// All subscribers of a device share one poll loop, which runs from the first of them
+// to the last one, so devices which are attached later have events too
+pub struct EventsHub {
+    devices_list: DevicesList,
+    poll_interval: Duration,
+    channels: Channels,
+}
+
+impl EventsHub {
+    pub fn new(devices_list: &DevicesList, poll_interval: Duration) -> Self {
+        Self {
+            devices_list: devices_list.clone(),
+            poll_interval,
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Returns events of a device starting with tags which are already in the field.
+    // None if there's no such device
+    pub fn subscribe(&self, device_id: &str) -> Option<(Vec<TagEvent>, Subscription)> {
+        let mut channels = self.channels.lock().unwrap();
+        if !channels.contains_key(device_id) {
+            let device = self.devices_list.device(device_id)?;
+            let channel = Channel::start(device_id, device, self.poll_interval);
+            channels.insert(device_id.to_string(), channel);
+        }
+        let channel = &channels[device_id];
+        // The poller doesn't send anything while the list is locked,
+        // so no event is lost or duplicated
+        let known = channel.known.lock().unwrap();
+        let receiver = channel.sender.subscribe();
+        let current = known
+            .iter()
+            .map(|tag| TagEvent::new(TagEventKind::Added, tag))
+            .collect();
+        let subscription = Subscription {
+            device_id: device_id.to_string(),
+            receiver: Some(receiver),
+            channels: self.channels.clone(),
+        };
+        Some((current, subscription))
+    }
+}
+
+// Events of a device for one subscriber. The channel of the device is removed
+// and its poller is stopped when the last subscription is dropped
+pub struct Subscription {
+    device_id: String,
+    // None only while it's dropped
+    receiver: Option<Receiver<TagEvent>>,
+    channels: Channels,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Result<TagEvent, RecvError> {
+        self.receiver.as_mut().unwrap().recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.receiver = None;
+        // New subscribers wait for the lock, so they don't get a stopped channel
+        let mut channels = self.channels.lock().unwrap();
+        let channel = match channels.get(&self.device_id) {
+            Some(channel) if channel.sender.receiver_count() == 0 => {
+                channels.remove(&self.device_id)
+            }
+            _ => None,
+        };
+        drop(channels);
+        if let Some(channel) = channel {
+            info!("The last subscriber of {} events has gone", self.device_id);
+            channel.stop();
+        }
+    }
+}
+
+// Compares two inventories of a reader field by tag UIDs
+fn diff(old: &[RawTag], new: &[RawTag]) -> Vec<TagEvent> {
+    let mut events = Vec::new();
+    for tag in new {
+        match old.iter().find(|t| t.uid == tag.uid) {
+            None => events.push(TagEvent::new(TagEventKind::Added, tag)),
+            Some(t) if t.memory != tag.memory => {
+                events.push(TagEvent::new(TagEventKind::Changed, tag))
+            }
+            Some(_) => (),
+        }
+    }
+    for tag in old {
+        if !new.iter().any(|t| t.uid == tag.uid) {
+            debug!("Tag {} has left the field", to_hex_string(&tag.uid));
+            // The last known memory is reported, so a client knows which item is gone
+            events.push(TagEvent::new(TagEventKind::Removed, tag));
+        }
+    }
+    events
+}
+
+#[test]
+fn inventory_diff() {
+    let tag = |uid: u8, memory: u8| RawTag {
+        uid: vec![uid],
+        dsfid: None,
+        memory: vec![memory; 32],
+    };
+    let old = vec![tag(1, 0), tag(2, 0), tag(3, 0)];
+    let new = vec![tag(2, 0), tag(3, 1), tag(4, 0)];
+
+    let kinds: Vec<TagEventKind> = diff(&old, &new).iter().map(|e| e.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            TagEventKind::Changed,
+            TagEventKind::Added,
+            TagEventKind::Removed
+        ]
+    );
+    assert!(diff(&new, &new).is_empty());
+}
+
+#[test]
+fn channel_lives_while_subscribed() {
+    use crate::config::Config;
+    use rocket::serde::json;
+
+    let config: Config =
+        json::from_str(r#"{"log_to_file": false, "devices": [{"driver": "simulator"}]}"#).unwrap();
+    let hub = EventsHub::new(&DevicesList::new(&config), Duration::from_millis(10));
+    assert!(hub.subscribe("Unknown").is_none());
+    let channels = || hub.channels.lock().unwrap().len();
+
+    let (_, first) = hub.subscribe("Simulator").unwrap();
+    let (_, second) = hub.subscribe("Simulator").unwrap();
+    assert_eq!(channels(), 1);
+    drop(first);
+    assert_eq!(channels(), 1);
+    drop(second);
+    assert_eq!(channels(), 0);
+
+    // A new subscriber starts polling again
+    let (_, third) = hub.subscribe("Simulator").unwrap();
+    assert_eq!(channels(), 1);
+    drop(third);
+    assert_eq!(channels(), 0);
+}
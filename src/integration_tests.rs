@@ -0,0 +1,1032 @@
+// HTTP API tests against the simulated reader, see devices::simulator
+use crate::config::Config;
+use crate::devices::{test_device::TestDevice, DeviceState, DevicesList};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::blocking::{Client, LocalRequest, LocalResponse};
+use rocket::serde::json::{self, serde_json::json, Value};
+use std::{
+    fs,
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+const LOCAL: &str = "127.0.0.1:50000";
+const REMOTE: &str = "203.0.113.5:50000";
+const SITE: &str = "https://library.example.org";
+const OTHER_SITE: &str = "https://catalog.example.org";
+// Tag of the simulator without a scenario
+const TAG_ID: &str = "E004015012345678";
+
+fn client(devices: Value) -> Client {
+    configured(json!({ "devices": devices }))
+}
+
+// Client of the server with the given config fields
+fn configured(mut config: Value) -> Client {
+    config["log_to_file"] = json!(false);
+    let config: Config = json::from_value(config).unwrap();
+    let client = Client::tracked(crate::rocket(config)).unwrap();
+
+    // Devices are connected on their own threads
+    let devices = client.rocket().state::<DevicesList>().unwrap();
+    let start = Instant::now();
+    while devices
+        .get()
+        .values()
+        .any(|d| d.status().state != DeviceState::Connected)
+    {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Devices aren't connected"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    client
+}
+
+fn simulator() -> Client {
+    client(json!([{ "driver": "simulator", "name": "Desk" }]))
+}
+
+fn from<'c>(request: LocalRequest<'c>, addr: &str) -> LocalRequest<'c> {
+    request.remote(addr.parse::<SocketAddr>().unwrap())
+}
+
+// Simulator which is allowed only for one website with its own token
+fn with_origins() -> Client {
+    configured(json!({
+        "devices": [{ "driver": "simulator" }],
+        "allowed_origins": [SITE],
+        "api_token": "shared",
+        "origin_tokens": { OTHER_SITE: "other" },
+    }))
+}
+
+fn write_tags<'c>(client: &'c Client, device_id: &str, item_id: &str) -> LocalResponse<'c> {
+    from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!(
+            "action=writeTags&deviceId={device_id}&id={TAG_ID}&itemId={item_id}\
+             &type=1&libraryId=RU-12&itemSize=1&indexInItemPack=1"
+        ))
+        .dispatch()
+}
+
+fn assert_json(response: LocalResponse<'_>, status: Status, expected: Value) {
+    assert_eq!(response.status(), status);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some("*")
+    );
+    let body: Value = json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(body, expected);
+}
+
+fn item(item_id: &str, usage_type: u8, library_id: &str) -> Value {
+    json!([{
+        "id": item_id,
+        "type": usage_type,
+        "tags": [{
+            "tagId": TAG_ID,
+            "itemId": item_id,
+            "format": 61,
+            "type": usage_type,
+            "itemSize": 1,
+            "indexInItemPack": 1,
+            "libraryId": library_id,
+            "status": "ok",
+        }],
+    }])
+}
+
+#[test]
+fn devices_list() {
+    let client = simulator();
+    for request in [
+        client.get("/rfid/?action=getDevicesList"),
+        client.options("/rfid/?action=getDevicesList"),
+    ] {
+        let response = from(request, LOCAL).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut body: Value = json::from_str(&response.into_string().unwrap()).unwrap();
+        // It's the time when the device has connected
+        assert!(body[0]["stateChangedAt"].as_u64().unwrap() > 0);
+        body[0]["stateChangedAt"] = json!(0);
+        assert_eq!(
+            body,
+            json!([{
+                "id": "Simulator",
+                "title": "Desk",
+                "isOnline": true,
+                "manualConnectIsNeeded": false,
+                "multiTagIsSupported": true,
+                "isError": false,
+                "isReadOnly": false,
+                "compoundDataIsSupported": true,
+                "securityIsSupported": true,
+                "lockIsSupported": true,
+                "tagInfoIsSupported": true,
+                "beepIsSupported": true,
+                "stateChangedAt": 0,
+            }])
+        );
+    }
+}
+
+#[test]
+fn items_list() {
+    let client = simulator();
+    for request in [
+        client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+        client.options("/rfid/?action=getItemsList&deviceId=Simulator"),
+    ] {
+        let response = from(request, LOCAL).dispatch();
+        assert_json(response, Status::Ok, item("1234567890", 8, "RU-123"));
+    }
+
+    let response = from(
+        client.get("/rfid/?action=getItemsList&deviceId=Unknown"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn tags_security() {
+    let client = simulator();
+    for request in [
+        client.get("/rfid/?action=getTagsSecurity&deviceId=Simulator"),
+        client.options("/rfid/?action=getTagsSecurity&deviceId=Simulator"),
+    ] {
+        let response = from(request, LOCAL).dispatch();
+        assert_json(
+            response,
+            Status::Ok,
+            json!([{ "tagId": TAG_ID, "isSecuritySupported": true, "isSecured": true }]),
+        );
+    }
+
+    let set_security = |params: &str| {
+        from(client.post("/rfid/"), LOCAL)
+            .header(ContentType::Form)
+            .body(format!("action=setTagsSecurity&{params}"))
+            .dispatch()
+    };
+    let response = set_security(&format!("deviceId=Simulator&id={TAG_ID}&isSecured=false"));
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{ "id": TAG_ID, "success": true, "error": null }]),
+    );
+    let response = from(
+        client.get("/rfid/?action=getTagsSecurity&deviceId=Simulator"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{ "tagId": TAG_ID, "isSecuritySupported": true, "isSecured": false }]),
+    );
+
+    // Every tag needs its own flag
+    let response = set_security(&format!(
+        "deviceId=Simulator&id={TAG_ID}&id=E004015000000001&isSecured=true"
+    ));
+    assert_eq!(response.status(), Status::BadRequest);
+    let response = set_security(&format!("deviceId=Simulator&id={TAG_ID}"));
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = set_security(&format!("deviceId=Unknown&id={TAG_ID}&isSecured=true"));
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn tag_info() {
+    let client = simulator();
+    let response = from(
+        client.get(format!(
+            "/rfid/?action=getTagInfo&deviceId=Simulator&tagId={}",
+            TAG_ID.to_lowercase()
+        )),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!({
+            "tagId": TAG_ID,
+            "dsfid": 0,
+            "afi": 7,
+            "blockSize": 4,
+            "blockCount": 28,
+            "icReference": 1,
+            "manufacturer": "NXP Semiconductors",
+        }),
+    );
+
+    for (tag_id, status) in [
+        ("E004015000000001", Status::NotFound),
+        ("E0040150XYZ", Status::BadRequest),
+    ] {
+        let response = from(
+            client.get(format!(
+                "/rfid/?action=getTagInfo&deviceId=Simulator&tagId={tag_id}"
+            )),
+            LOCAL,
+        )
+        .dispatch();
+        assert_eq!(response.status(), status);
+    }
+    let response = from(
+        client.get(format!(
+            "/rfid/?action=getTagInfo&deviceId=Unknown&tagId={TAG_ID}"
+        )),
+        LOCAL,
+    )
+    .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn write_and_read_back() {
+    let client = simulator();
+    let response = from(client.options("/rfid/"), LOCAL).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string(), Some(String::new()));
+
+    let response = write_tags(&client, "Simulator", "555");
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{ "id": TAG_ID, "success": true, "error": null }]),
+    );
+    let response = from(
+        client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(response, Status::Ok, item("555", 1, "RU-12"));
+
+    // Item fields are validated before anything is written
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!(
+            "action=writeTags&deviceId=Simulator&id={TAG_ID}&itemId=556\
+             &type=1&libraryId=RU&itemSize=1&indexInItemPack=1"
+        ))
+        .dispatch();
+    assert_json(
+        response,
+        Status::BadRequest,
+        json!({
+            "type": "Params Error",
+            "message": "Item 0, libraryId: Library id must look like RU-293",
+        }),
+    );
+}
+
+#[test]
+fn tags_are_written_only_by_their_ids() {
+    let client = simulator();
+    // The only tag on the reader isn't written instead of a missing one
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(
+            "action=writeTags&deviceId=Simulator&id=E004015000000001&itemId=555\
+             &type=1&libraryId=RU-12&itemSize=1&indexInItemPack=1",
+        )
+        .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{
+            "id": "E004015000000001",
+            "success": false,
+            "error": { "type": "Write Error", "message": "Tag not found" },
+        }]),
+    );
+    let response = from(
+        client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(response, Status::Ok, item("1234567890", 8, "RU-123"));
+
+    // Responses keep the order of the items
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!(
+            "action=writeTags&deviceId=Simulator\
+             &id=E004015000000001&itemId=555&type=1&libraryId=RU-12&itemSize=2&indexInItemPack=1\
+             &id={TAG_ID}&itemId=555&type=1&libraryId=RU-12&itemSize=2&indexInItemPack=2"
+        ))
+        .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!([
+            {
+                "id": "E004015000000001",
+                "success": false,
+                "error": { "type": "Write Error", "message": "Tag not found" },
+            },
+            { "id": TAG_ID, "success": true, "error": null },
+        ]),
+    );
+
+    // Without id the only tag is written
+    let response = write_tags(&client, "Simulator", "556");
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{ "id": TAG_ID, "success": true, "error": null }]),
+    );
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(
+            "action=writeTags&deviceId=Simulator&id=&itemId=557\
+             &type=1&libraryId=RU-12&itemSize=1&indexInItemPack=1",
+        )
+        .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{ "id": TAG_ID, "success": true, "error": null }]),
+    );
+    let response = from(
+        client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(response, Status::Ok, item("557", 1, "RU-12"));
+}
+
+#[test]
+fn verification_after_writing() {
+    let path = std::env::temp_dir().join(format!("rfid_sfl_verify_{}.json", std::process::id()));
+    let write_scenario = |faults: &str| {
+        let scenario = format!(r#"{{"tags": [{{"uid": "{TAG_ID}"}}], "faults": [{faults}]}}"#);
+        fs::write(&path, scenario).unwrap();
+    };
+    write_scenario("");
+    let scenario = path.to_string_lossy().into_owned();
+    let client = configured(json!({
+        "devices": [{ "driver": "simulator", "scenario": scenario }],
+        "verify_after_writing": true,
+    }));
+    let device_id = format!("Simulator@{scenario}");
+
+    let response = write_tags(&client, &device_id, "555");
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{ "id": TAG_ID, "success": true, "error": null }]),
+    );
+
+    write_scenario(r#""corruptWrite""#);
+    let response = write_tags(&client, &device_id, "556");
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{
+            "id": TAG_ID,
+            "success": false,
+            "error": {
+                "type": "Verification Error",
+                "message": "Written data doesn't match the sent one",
+            },
+        }]),
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn locking_after_writing() {
+    let path = std::env::temp_dir().join(format!("rfid_sfl_lock_{}.json", std::process::id()));
+    let write_scenario = |faults: &str| {
+        let scenario = format!(r#"{{"tags": [{{"uid": "{TAG_ID}"}}], "faults": [{faults}]}}"#);
+        fs::write(&path, scenario).unwrap();
+    };
+    write_scenario(r#""lockFailure""#);
+    let scenario = path.to_string_lossy().into_owned();
+    let client = configured(json!({
+        "devices": [{ "driver": "simulator", "scenario": scenario }],
+    }));
+    let lock_tags = |item_id: &str| {
+        from(client.post("/rfid/"), LOCAL)
+            .header(ContentType::Form)
+            .body(format!(
+                "action=writeTags&deviceId=Simulator@{scenario}&id={TAG_ID}&itemId={item_id}\
+                 &type=1&libraryId=RU-12&itemSize=1&indexInItemPack=1&lockBlocks=true"
+            ))
+            .dispatch()
+    };
+
+    // The data is written, but the tag isn't locked as requested
+    assert_json(
+        lock_tags("555"),
+        Status::Ok,
+        json!([{
+            "id": TAG_ID,
+            "success": false,
+            "error": { "type": "Simulator Error", "message": "Simulated lock failure" },
+            "locked": false,
+        }]),
+    );
+
+    write_scenario("");
+    assert_json(
+        lock_tags("556"),
+        Status::Ok,
+        json!([{ "id": TAG_ID, "success": true, "error": null, "locked": true }]),
+    );
+    // Locked blocks can't be written anymore
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!(
+            "action=writeTags&deviceId=Simulator@{scenario}&id={TAG_ID}&itemId=557\
+             &type=1&libraryId=RU-12&itemSize=1&indexInItemPack=1"
+        ))
+        .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{
+            "id": TAG_ID,
+            "success": false,
+            "error": { "type": "Simulator Error", "message": "Blocks of the tag are locked" },
+        }]),
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn raw_blocks() {
+    let read = |client: &Client, addr: &str, query: &str| {
+        from(
+            client.get(format!(
+                "/rfid/?action=readBlocks&deviceId=Simulator&tagId={TAG_ID}&{query}"
+            )),
+            addr,
+        )
+        .dispatch()
+        .status()
+    };
+    let write = |client: &Client, addr: &str, params: &str| {
+        from(client.post("/rfid/"), addr)
+            .header(ContentType::Form)
+            .body(format!("action=writeBlocks&deviceId=Simulator&{params}"))
+            .dispatch()
+            .status()
+    };
+    let block_10 = format!("id={TAG_ID}&firstBlock=10&data=0102030405060708");
+
+    // The actions are disabled by default
+    let client = simulator();
+    assert_eq!(
+        read(&client, LOCAL, "firstBlock=0&count=1"),
+        Status::Forbidden
+    );
+    assert_eq!(write(&client, LOCAL, &block_10), Status::Forbidden);
+
+    let client = configured(json!({
+        "devices": [{ "driver": "simulator" }],
+        "raw_blocks_access": true,
+        "allowed_networks": ["10.0.0.0/24"],
+    }));
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!("action=writeBlocks&deviceId=Simulator&{block_10}"))
+        .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!({ "tagId": TAG_ID, "firstBlock": 10, "data": "0102030405060708" }),
+    );
+    let response = from(
+        client.get(format!(
+            "/rfid/?action=readBlocks&deviceId=Simulator&tagId={}&firstBlock=10&count=3",
+            TAG_ID.to_lowercase()
+        )),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(
+        response,
+        Status::Ok,
+        json!({ "tagId": TAG_ID, "firstBlock": 10, "data": "010203040506070800000000" }),
+    );
+
+    // Clients of allowed networks can't use them
+    let remote = "10.0.0.5:50000";
+    assert_eq!(
+        read(&client, remote, "firstBlock=10&count=1"),
+        Status::Forbidden
+    );
+    assert_eq!(write(&client, remote, &block_10), Status::Forbidden);
+
+    // Ranges of blocks
+    for (query, status) in [
+        ("firstBlock=250&count=10", Status::BadRequest),
+        ("firstBlock=0&count=0", Status::BadRequest),
+        ("firstBlock=27&count=2", Status::NotFound),
+        ("firstBlock=27&count=1", Status::Ok),
+    ] {
+        assert_eq!(read(&client, LOCAL, query), status, "{query}");
+    }
+    let params = format!("id={TAG_ID}&firstBlock=255&data=0102030405060708");
+    assert_eq!(write(&client, LOCAL, &params), Status::BadRequest);
+
+    // Hex of tag ids and data
+    let response = from(
+        client.get("/rfid/?action=readBlocks&deviceId=Simulator&tagId=E0XX&firstBlock=0&count=1"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    for params in [
+        "id=E0XX&firstBlock=10&data=01020304".to_string(),
+        format!("id={TAG_ID}&firstBlock=10&data=010203"),
+        format!("id={TAG_ID}&firstBlock=10&data=0102030G"),
+        format!("id={TAG_ID}&firstBlock=10&data="),
+        format!("id={TAG_ID}&data=01020304"),
+    ] {
+        assert_eq!(
+            write(&client, LOCAL, &params),
+            Status::BadRequest,
+            "{params}"
+        );
+    }
+}
+
+#[test]
+fn read_only_device() {
+    let client = client(json!([{ "driver": "simulator", "read_only": true }]));
+    let response = write_tags(&client, "Simulator", "555");
+    assert_json(
+        response,
+        Status::Ok,
+        json!([{
+            "id": TAG_ID,
+            "success": false,
+            "error": { "type": "Read Only", "message": "Reader is read-only" },
+        }]),
+    );
+}
+
+// A reader without optional features answers 501 with the same error
+#[test]
+fn unsupported_features() {
+    let client = client(json!([]));
+    let devices = client.rocket().state::<DevicesList>().unwrap();
+    devices.add("Basic", Box::new(TestDevice { is_basic: true }));
+    let not_supported = |response: LocalResponse<'_>, message: &str| {
+        assert_json(
+            response,
+            Status::NotImplemented,
+            json!({ "type": "Not Supported", "message": message }),
+        );
+    };
+
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!(
+            "action=setTagsSecurity&deviceId=Basic&id={TAG_ID}&isSecured=true"
+        ))
+        .dispatch();
+    not_supported(response, "Reader doesn't support changing security of tags");
+
+    let response = from(client.post("/rfid/"), LOCAL)
+        .header(ContentType::Form)
+        .body(format!(
+            "action=writeTags&deviceId=Basic&id={TAG_ID}&itemId=555&type=1\
+             &libraryId=RU-12&itemSize=1&indexInItemPack=1&lockBlocks=true"
+        ))
+        .dispatch();
+    not_supported(response, "Reader doesn't support locking blocks");
+
+    let response = from(
+        client.get(format!(
+            "/rfid/?action=getTagInfo&deviceId=Basic&tagId={TAG_ID}"
+        )),
+        LOCAL,
+    )
+    .dispatch();
+    not_supported(
+        response,
+        "Reader doesn't support reading system information of tags",
+    );
+}
+
+#[test]
+fn websocket_handshake() {
+    let client = simulator();
+    let handshake = |headers: &[(&'static str, &'static str)]| {
+        let mut request = from(client.get("/rfid/ws"), LOCAL);
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, *value));
+        }
+        request.dispatch()
+    };
+    // The example of RFC 6455
+    let key = ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==");
+    let upgrade = ("Upgrade", "websocket");
+
+    let response = handshake(&[
+        key,
+        ("Upgrade", "WebSocket"),
+        ("Connection", "keep-alive, Upgrade"),
+        ("Sec-WebSocket-Version", "13"),
+    ]);
+    assert_eq!(
+        response.headers().get_one("Sec-WebSocket-Accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+
+    for version in [None, Some("8")] {
+        let mut headers = vec![key, upgrade, ("Connection", "Upgrade")];
+        headers.extend(version.map(|v| ("Sec-WebSocket-Version", v)));
+        let response = handshake(&headers);
+        assert_eq!(response.status(), Status::UpgradeRequired);
+        assert_eq!(
+            response.headers().get_one("Sec-WebSocket-Version"),
+            Some("13")
+        );
+    }
+
+    for headers in [
+        vec![key, ("Connection", "Upgrade")],
+        vec![key, upgrade, ("Connection", "keep-alive")],
+        vec![upgrade, ("Connection", "Upgrade")],
+        vec![key, ("Upgrade", "h2c"), ("Connection", "Upgrade")],
+    ] {
+        let mut headers = headers;
+        headers.push(("Sec-WebSocket-Version", "13"));
+        let response = handshake(&headers);
+        assert_eq!(response.status(), Status::BadRequest, "{headers:?}");
+        assert_eq!(response.headers().get_one("Sec-WebSocket-Accept"), None);
+    }
+}
+
+#[test]
+fn remote_clients_are_forbidden() {
+    let client = simulator();
+    let requests = [
+        client.get("/rfid/?action=getDevicesList"),
+        client.options("/rfid/?action=getDevicesList"),
+        client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+        client.options("/rfid/?action=getItemsList&deviceId=Simulator"),
+        client.options("/rfid/"),
+        client
+            .post("/rfid/")
+            .header(ContentType::Form)
+            .body(format!("action=writeTags&deviceId=Simulator&id={TAG_ID}")),
+    ];
+    for request in requests {
+        let response = from(request, REMOTE).dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(response.into_string(), Some(String::new()));
+    }
+
+    // Nothing has been written
+    let response = from(
+        client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+        LOCAL,
+    )
+    .dispatch();
+    assert_json(response, Status::Ok, item("1234567890", 8, "RU-123"));
+}
+
+#[test]
+fn allowed_origins() {
+    let client = with_origins();
+    let cases = [(SITE, "shared"), (OTHER_SITE, "other")];
+    for (origin, token) in cases {
+        let response = from(
+            client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+            LOCAL,
+        )
+        .header(Header::new("Origin", origin))
+        .header(Header::new("Authorization", format!("Bearer {token}")))
+        .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            Some(origin)
+        );
+        assert_eq!(response.headers().get_one("Vary"), Some("Origin"));
+    }
+
+    // EventSource and WebSocket of browsers pass the token in the query
+    let response = from(
+        client.get("/rfid/?action=getDevicesList&token=shared"),
+        LOCAL,
+    )
+    .header(Header::new("Origin", SITE))
+    .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = from(
+        client.get("/rfid/events?deviceId=Simulator&token=shared"),
+        LOCAL,
+    )
+    .header(Header::new("Origin", SITE))
+    .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some(SITE)
+    );
+
+    // Preflight requests of browsers don't have credentials
+    let response = from(client.options("/rfid/"), LOCAL)
+        .header(Header::new("Origin", SITE))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some(SITE)
+    );
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Headers"),
+        Some("Authorization")
+    );
+}
+
+#[test]
+fn unknown_origins_and_tokens_are_forbidden() {
+    let client = with_origins();
+    let cases = [
+        // Unknown website
+        (Some("https://evil.example.com"), Some("shared")),
+        // Token of the other website
+        (Some(SITE), Some("other")),
+        (Some(OTHER_SITE), Some("shared")),
+        (Some(SITE), None),
+        // Not a browser
+        (None, None),
+        (None, Some("other")),
+    ];
+    for (origin, token) in cases {
+        let mut request = from(
+            client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+            LOCAL,
+        );
+        if let Some(origin) = origin {
+            request = request.header(Header::new("Origin", origin));
+        }
+        if let Some(token) = token {
+            request = request.header(Header::new("Authorization", format!("Bearer {token}")));
+        }
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::Forbidden, "{origin:?} {token:?}");
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            None
+        );
+        assert_eq!(response.into_string(), Some(String::new()));
+    }
+
+    let response = from(client.options("/rfid/"), LOCAL)
+        .header(Header::new("Origin", "https://evil.example.com"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Not a browser, but has the token
+    let response = from(client.get("/rfid/?action=getDevicesList"), LOCAL)
+        .header(Header::new("Authorization", "Bearer shared"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+// Simulator with websites paired by the user before
+fn with_paired(path: &std::path::Path) -> Client {
+    std::fs::write(
+        path,
+        json!({
+            "https://reader.example.org": "read",
+            "https://writer.example.org": "write",
+            "https://denied.example.org": "deny",
+        })
+        .to_string(),
+    )
+    .unwrap();
+    configured(json!({
+        "devices": [{ "driver": "simulator" }],
+        "pair_origins": true,
+        "permissions_file": path,
+    }))
+}
+
+fn permissions_file(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("rfid_sfl_{name}_{}.json", std::process::id()))
+}
+
+#[test]
+fn paired_origins() {
+    let path = permissions_file("paired_origins");
+    let client = with_paired(&path);
+    let cases = [
+        ("https://reader.example.org", Status::Ok, Status::Forbidden),
+        ("https://writer.example.org", Status::Ok, Status::Ok),
+        (
+            "https://denied.example.org",
+            Status::Forbidden,
+            Status::Forbidden,
+        ),
+    ];
+    for (origin, read_status, write_status) in cases {
+        let response = from(
+            client.get("/rfid/?action=getItemsList&deviceId=Simulator"),
+            LOCAL,
+        )
+        .header(Header::new("Origin", origin))
+        .dispatch();
+        assert_eq!(response.status(), read_status, "{origin}");
+        let allow_origin = Some(origin).filter(|_| read_status == Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            allow_origin
+        );
+
+        let response = from(client.post("/rfid/"), LOCAL)
+            .header(Header::new("Origin", origin))
+            .header(ContentType::Form)
+            .body(format!(
+                "action=setTagsSecurity&deviceId=Simulator&id={TAG_ID}&isSecured=true"
+            ))
+            .dispatch();
+        assert_eq!(response.status(), write_status, "{origin}");
+    }
+
+    // Browsers ask before writing without credentials
+    let response = from(client.options("/rfid/"), LOCAL)
+        .header(Header::new("Origin", "https://reader.example.org"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = from(client.options("/rfid/"), LOCAL)
+        .header(Header::new("Origin", "https://denied.example.org"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn permissions_page() {
+    let path = permissions_file("permissions_page");
+    let client = with_paired(&path);
+    let page = |host: &str, addr: &str| {
+        from(client.get("/permissions"), addr)
+            .header(Header::new("Host", host.to_string()))
+            .dispatch()
+    };
+    let response = page("localhost:21646", LOCAL);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let page_html = response.into_string().unwrap();
+    assert!(page_html.contains("https://reader.example.org"));
+    assert!(page_html.contains("<option selected>deny</option>"));
+    for host in ["127.0.0.1:21646", "[::1]:21646"] {
+        assert_eq!(page(host, LOCAL).status(), Status::Ok, "{host}");
+    }
+
+    // Websites can't change their permissions, even allowed ones
+    let change = |host: &str, origin: Option<&str>, addr: &str, body: &str| {
+        let mut request = from(client.post("/permissions"), addr)
+            .header(Header::new("Host", host.to_string()))
+            .header(ContentType::Form)
+            .body(body);
+        if let Some(origin) = origin {
+            request = request.header(Header::new("Origin", origin.to_string()));
+        }
+        request.dispatch().status()
+    };
+    let body = "origin=https://reader.example.org&permission=write";
+    assert_eq!(
+        change(
+            "localhost:21646",
+            Some("https://writer.example.org"),
+            LOCAL,
+            body
+        ),
+        Status::Forbidden
+    );
+    assert_eq!(
+        change(
+            "localhost:21646",
+            Some("http://localhost:21646"),
+            REMOTE,
+            body
+        ),
+        Status::Forbidden
+    );
+    assert_eq!(page("localhost:21646", REMOTE).status(), Status::Forbidden);
+
+    // A website whose name points to the computer has another host
+    for host in [
+        "evil.example.org:21646",
+        "localhost:8080",
+        "127.0.0.2:21646",
+    ] {
+        assert_eq!(page(host, LOCAL).status(), Status::Forbidden, "{host}");
+        let origin = format!("http://{host}");
+        assert_eq!(
+            change(host, Some(&origin), LOCAL, body),
+            Status::Forbidden,
+            "{host}"
+        );
+    }
+    assert_eq!(
+        from(client.get("/permissions"), LOCAL).dispatch().status(),
+        Status::Forbidden
+    );
+
+    // Changes without the origin are rejected
+    assert_eq!(
+        change("localhost:21646", None, LOCAL, body),
+        Status::Forbidden
+    );
+
+    // Forms of the page have the origin of the server
+    let own =
+        |host: &'static str, body: &str| change(host, Some(&format!("http://{host}")), LOCAL, body);
+    assert_eq!(own("localhost:21646", body), Status::SeeOther);
+    assert_eq!(
+        own(
+            "127.0.0.1:21646",
+            "origin=https://denied.example.org&revoke=true"
+        ),
+        Status::SeeOther
+    );
+    assert_eq!(
+        own(
+            "[::1]:21646",
+            "origin=https://unknown.example.org&revoke=true"
+        ),
+        Status::NotFound
+    );
+    let permissions: Value = json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+        permissions,
+        json!({
+            "https://reader.example.org": "write",
+            "https://writer.example.org": "write",
+        })
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn loopback_and_allowed_networks() {
+    let client = simulator();
+    let status = |client: &Client, addr: &str| {
+        from(client.get("/rfid/?action=getDevicesList"), addr)
+            .dispatch()
+            .status()
+    };
+    for addr in ["127.0.0.2:50000", "[::1]:50000", "[::ffff:127.0.0.1]:50000"] {
+        assert_eq!(status(&client, addr), Status::Ok, "{addr}");
+    }
+    for addr in [
+        "10.0.0.5:50000",
+        "[fd00::5]:50000",
+        "[::ffff:10.0.0.5]:50000",
+    ] {
+        assert_eq!(status(&client, addr), Status::Forbidden, "{addr}");
+    }
+    // Proxy headers can't make a remote client local
+    let response = from(client.get("/rfid/?action=getDevicesList"), "10.0.0.5:50000")
+        .header(Header::new("X-Forwarded-For", "127.0.0.1"))
+        .header(Header::new("X-Real-IP", "127.0.0.1"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let client = configured(json!({
+        "devices": [{ "driver": "simulator" }],
+        "allowed_networks": ["10.0.0.0/24", "fd00::/8"],
+    }));
+    for addr in [
+        "10.0.0.5:50000",
+        "[fd00::5]:50000",
+        "[::ffff:10.0.0.5]:50000",
+        LOCAL,
+    ] {
+        assert_eq!(status(&client, addr), Status::Ok, "{addr}");
+    }
+    for addr in ["10.0.1.5:50000", REMOTE] {
+        assert_eq!(status(&client, addr), Status::Forbidden, "{addr}");
+    }
+    // The page of permissions is only for the computer itself
+    let response = from(client.get("/permissions"), "10.0.0.5:50000").dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+}
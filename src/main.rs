@@ -3,14 +3,20 @@
 extern crate rocket;
 mod config;
 mod devices;
+mod events;
+#[cfg(test)]
+mod integration_tests;
+mod permissions;
 mod rfid_items;
 mod routes;
 
 use config::Config;
 use devices::DevicesList;
+use events::EventsHub;
 use log::*;
+use permissions::Permissions;
 use simplelog::*;
-use std::{fs::OpenOptions, net::IpAddr, str::FromStr};
+use std::{fs::OpenOptions, net::IpAddr, str::FromStr, time::Duration};
 
 const LOGFILE: &str = "rfid_sfl.log";
 
@@ -89,7 +95,11 @@ fn launch() -> _ {
             Config::default()
         }
     };
+    rocket(config)
+}
 
+// Server with devices from the config
+fn rocket(config: Config) -> rocket::Rocket<rocket::Build> {
     let address = IpAddr::from_str(config.address());
     if address.is_err() {
         error!("Unable to parse the IP address");
@@ -101,26 +111,50 @@ fn launch() -> _ {
         port: config.port(),
         log_level: rocket::config::LogLevel::Off, // using our own logger
         cli_colors: !config.log_to_file(),
-        workers: 1, // just for safety
+        // Default number of workers. Devices run their commands one by one
+        // on their own threads, so readers aren't accessed concurrently anyway
         ..Default::default()
     };
 
-    let devices_list = DevicesList::new();
+    let devices_list = DevicesList::new(&config);
+    let events_hub = EventsHub::new(
+        &devices_list,
+        Duration::from_millis(config.events_poll_interval()),
+    );
     rocket::build()
         .configure(rocket_config)
-        .mount("/", routes![crate::routes::index::handler,])
+        .mount(
+            "/",
+            routes![
+                crate::routes::index::handler,
+                crate::routes::permissions::handler,
+                crate::routes::permissions::change_handler,
+            ],
+        )
         .mount(
             "/rfid",
             routes![
                 crate::routes::rfid_index::handler,
+                crate::routes::events::handler,
                 crate::routes::get_devices_list::handler,
                 crate::routes::get_devices_list::handler_options,
                 crate::routes::get_items_list::handler,
                 crate::routes::get_items_list::handler_options,
+                crate::routes::get_tag_info::handler,
+                crate::routes::get_tag_info::handler_options,
+                crate::routes::get_tags_security::handler,
+                crate::routes::get_tags_security::handler_options,
+                crate::routes::raw_blocks::read_handler,
+                crate::routes::raw_blocks::read_handler_options,
                 crate::routes::write_tags::handler,
                 crate::routes::write_tags::handler_options,
+                crate::routes::websocket::handler,
+                crate::routes::websocket::handler_not_upgraded,
             ],
         )
+        .attach(crate::routes::access::cors())
         .manage(devices_list)
+        .manage(events_hub)
+        .manage(Permissions::load(config.permissions_file()))
         .manage(config)
 }
@@ -0,0 +1,199 @@
+// Decisions of the user about websites which aren't listed in the config.
+// The first time a new origin uses the server, the user is asked whether it may read
+// and write cards. The answers are kept in a file next to config.json
+use log::*;
+use rocket::serde::{json, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Permission {
+    Deny,
+    Read,
+    Write,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deny => "deny",
+            Self::Read => "read",
+            Self::Write => "write",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "deny" => Ok(Self::Deny),
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            _ => Err(()),
+        }
+    }
+}
+
+// Shows a question to the user. None if it can't be shown
+type Prompt = fn(&str) -> Option<bool>;
+
+#[derive(Debug, Clone)]
+pub struct Permissions {
+    path: PathBuf,
+    origins: Arc<Mutex<BTreeMap<String, Permission>>>,
+    // Only one question is shown at a time
+    prompt_lock: Arc<Mutex<()>>,
+    prompt: Prompt,
+}
+
+impl Permissions {
+    // Reads the file. It's created with the first decision
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let origins = match fs::read_to_string(&path) {
+            Ok(contents) => json::from_str(&contents).unwrap_or_else(|e| {
+                error!(
+                    "Unable to parse {}: {e}. No websites are paired",
+                    path.display()
+                );
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path,
+            origins: Arc::new(Mutex::new(origins)),
+            prompt_lock: Arc::new(Mutex::new(())),
+            prompt: ask_user,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_prompt(mut self, prompt: Prompt) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    pub fn get(&self, origin: &str) -> Option<Permission> {
+        self.origins.lock().unwrap().get(origin).copied()
+    }
+
+    pub fn list(&self) -> BTreeMap<String, Permission> {
+        self.origins.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, origin: &str, permission: Permission) {
+        let mut origins = self.origins.lock().unwrap();
+        origins.insert(origin.to_string(), permission);
+        info!("Permission of origin {origin}: {}", permission.as_str());
+        self.save(&origins);
+    }
+
+    // Returns false if the origin hasn't been paired
+    pub fn revoke(&self, origin: &str) -> bool {
+        let mut origins = self.origins.lock().unwrap();
+        if origins.remove(origin).is_none() {
+            return false;
+        }
+        info!("Permission of origin {origin} is revoked");
+        self.save(&origins);
+        true
+    }
+
+    // Checks if the origin has the permission. The user is asked about a new origin
+    pub fn request(&self, origin: &str, needed: Permission) -> bool {
+        if let Some(permission) = self.get(origin) {
+            return permission >= needed;
+        }
+        let _lock = self.prompt_lock.lock().unwrap();
+        // It could be paired while another request was waiting for the user
+        if let Some(permission) = self.get(origin) {
+            return permission >= needed;
+        }
+
+        let host = origin.split_once("://").map_or(origin, |(_, host)| host);
+        let permission = rocket::tokio::task::block_in_place(|| {
+            match (self.prompt)(&format!(
+                "Разрешить сайту {host} доступ к считывателю RFID?"
+            ))? {
+                false => Some(Permission::Deny),
+                true => (self.prompt)(&format!("Разрешить сайту {host} записывать карты?")).map(
+                    |write| match write {
+                        true => Permission::Write,
+                        false => Permission::Read,
+                    },
+                ),
+            }
+        });
+        match permission {
+            Some(permission) => {
+                self.set(origin, permission);
+                permission >= needed
+            }
+            None => {
+                warn!("Unable to ask the user about origin {origin}");
+                false
+            }
+        }
+    }
+
+    fn save(&self, origins: &BTreeMap<String, Permission>) {
+        let contents = json::to_pretty_string(origins).unwrap();
+        if let Err(e) = fs::write(&self.path, contents) {
+            error!("Unable to write {}: {e}", self.path.display());
+        }
+    }
+}
+
+fn ask_user(text: &str) -> Option<bool> {
+    native_dialog::MessageDialog::new()
+        .set_title("RFID Server For Libraries")
+        .set_text(text)
+        .set_type(native_dialog::MessageType::Info)
+        .show_confirm()
+        .ok()
+}
+
+#[test]
+fn pairing() {
+    let path =
+        std::env::temp_dir().join(format!("rfid_sfl_permissions_{}.json", std::process::id()));
+    let _ = fs::remove_file(&path);
+    // Reading is allowed to every site except denied.example.org, writing only to writer.example.org
+    let permissions = Permissions::load(&path).with_prompt(|text| {
+        Some(match text.contains("записывать") {
+            true => text.contains("writer.example.org"),
+            false => !text.contains("denied.example.org"),
+        })
+    });
+
+    assert!(permissions.request("https://reader.example.org", Permission::Read));
+    assert!(!permissions.request("https://reader.example.org", Permission::Write));
+    assert!(permissions.request("https://writer.example.org", Permission::Write));
+    assert!(!permissions.request("https://denied.example.org", Permission::Read));
+
+    // The decisions are kept after restarts, and the user isn't asked again
+    let permissions = Permissions::load(&path).with_prompt(|_| panic!("The user is asked again"));
+    assert!(permissions.request("https://reader.example.org", Permission::Read));
+    assert!(!permissions.request("https://reader.example.org", Permission::Write));
+    assert!(permissions.request("https://writer.example.org", Permission::Write));
+    assert!(!permissions.request("https://denied.example.org", Permission::Read));
+    assert_eq!(permissions.list().len(), 3);
+
+    // Nothing is kept if the user can't be asked
+    assert!(permissions.revoke("https://reader.example.org"));
+    assert!(!permissions.revoke("https://reader.example.org"));
+    let permissions = Permissions::load(&path).with_prompt(|_| None);
+    assert!(!permissions.request("https://reader.example.org", Permission::Read));
+    assert_eq!(permissions.get("https://reader.example.org"), None);
+
+    fs::remove_file(&path).unwrap();
+}
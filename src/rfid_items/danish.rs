@@ -0,0 +1,451 @@
+use super::{from_hex_string, ItemError, TagDataModel, DANISH_FORMAT};
+use log::*;
+use std::ops::Range;
+
+// Size of the primary block in bytes. Layout: type of usage and version (byte 0),
+// number of parts (1), ordinal number (2), item id (3..19), CRC (19..21),
+// country (21..23) and library id (23..32). Optional blocks follow it
+pub const PRIMARY_BLOCK_SIZE: usize = 32;
+
+// Version of the data model and types of usage which are defined by it:
+// acquisition, for circulation, not for circulation, discarded and patron card
+const STANDART_VERSION: u8 = 1;
+const USAGE_TYPES: [u8; 5] = [0, 1, 2, 7, 8];
+
+// Optional (extension) block that follows the primary block.
+// Layout: length of the whole block (1 byte), block id (1 byte), data,
+// CRC of the previous bytes (2 bytes). A zero length byte ends the list.
+// The raw bytes are kept as is, so unknown and damaged blocks are written back untouched
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionalBlock {
+    raw: Vec<u8>,
+}
+
+impl OptionalBlock {
+    // Blocks are only kept as they're read, new ones are made by tests
+    #[cfg(test)]
+    pub fn new(id: u8, data: &[u8]) -> Result<Self, ItemError> {
+        if data.len() > u8::MAX as usize - 4 {
+            return Err(ItemError::TooLong {
+                field: "optional block",
+                max: u8::MAX as usize - 4,
+            });
+        }
+        let mut raw = Vec::with_capacity(data.len() + 4);
+        raw.push((data.len() + 4) as u8);
+        raw.push(id);
+        raw.extend_from_slice(data);
+        let crc = DanishRfidItem::calc_crc(&raw).to_be_bytes();
+        raw.push(crc[1]);
+        raw.push(crc[0]);
+        Ok(Self { raw })
+    }
+
+    pub fn id(&self) -> u8 {
+        self.raw[1]
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let len = self.raw.len();
+        let crc = self.raw[len - 2] as u16 | ((self.raw[len - 1] as u16) << 8);
+        crc == DanishRfidItem::calc_crc(&self.raw[..len - 2])
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DanishRfidItem {
+    card_id: Vec<u8>,
+    usage_type: u8,       // u4 in fact
+    standart_version: u8, // u4 in fact
+    number_of_parts: u8,
+    ordinal_number: u8,
+    item_id: String,    // max 16 chars long
+    country: String,    // max 2 chars long
+    library_id: String, // max 9 chars long, the rest of the primary block
+    optional_blocks: Vec<OptionalBlock>,
+}
+
+impl Default for DanishRfidItem {
+    fn default() -> Self {
+        Self {
+            card_id: vec![],
+            usage_type: 8,
+            standart_version: 1,
+            number_of_parts: 1,
+            ordinal_number: 1,
+            item_id: Default::default(),
+            country: Default::default(),
+            library_id: Default::default(),
+            optional_blocks: vec![],
+        }
+    }
+}
+
+impl DanishRfidItem {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ItemError> {
+        if bytes.len() < 23 {
+            return Err(ItemError::TooShort);
+        }
+
+        // Primary block is padded with zeros if we've got less bytes
+        let mut primary = bytes[..bytes.len().min(PRIMARY_BLOCK_SIZE)].to_vec();
+        primary.resize(PRIMARY_BLOCK_SIZE, 0);
+        let optional_blocks = if bytes.len() > PRIMARY_BLOCK_SIZE {
+            Self::parse_optional_blocks(&bytes[PRIMARY_BLOCK_SIZE..])
+        } else {
+            vec![]
+        };
+        let bytes = &primary[..];
+
+        // Checking checksum
+        let crc = bytes[19] as u16 | ((bytes[20] as u16) << 8);
+        let mut bytes_without_crc = Vec::from(bytes);
+        bytes_without_crc.remove(19);
+        bytes_without_crc.remove(19);
+        bytes_without_crc.push(0);
+        bytes_without_crc.push(0);
+        let true_crc = Self::calc_crc(&bytes_without_crc);
+        if crc != true_crc {
+            // Data of other models doesn't have the checksum at all
+            if !Self::has_layout(bytes) {
+                return Err(ItemError::InvalidField("primary block"));
+            }
+            info!("Wrong checksum!");
+            return Err(ItemError::BadCrc);
+        }
+
+        let usage_type = bytes[0] >> 4;
+        let standart_version = bytes[0] & 0x0F;
+        let number_of_parts = bytes[1];
+        let ordinal_number = bytes[2];
+
+        let item_id = String::from_utf8(Self::strip0s(&bytes[3..19]).to_vec())
+            .map_err(|_| ItemError::InvalidUtf8("item_id"))?;
+
+        let country = String::from_utf8(Self::strip0s(&bytes[21..23]).to_vec())
+            .map_err(|_| ItemError::InvalidUtf8("country"))?;
+
+        let library_id = String::from_utf8(Self::strip0s(&bytes[23..32]).to_vec())
+            .map_err(|_| ItemError::InvalidUtf8("library_id"))?;
+
+        Ok(Self {
+            card_id: vec![],
+            usage_type,
+            standart_version,
+            number_of_parts,
+            ordinal_number,
+            item_id,
+            country,
+            library_id,
+            optional_blocks,
+        })
+    }
+
+    // Whether a primary block looks like a Danish one: known version and type of usage,
+    // a valid set information and a country code of two letters, if it's set
+    fn has_layout(bytes: &[u8]) -> bool {
+        let (number_of_parts, ordinal_number) = (bytes[1], bytes[2]);
+        let country = &bytes[21..23];
+        bytes[0] & 0x0F == STANDART_VERSION
+            && USAGE_TYPES.contains(&(bytes[0] >> 4))
+            && (1..=number_of_parts).contains(&ordinal_number)
+            && (country == [0, 0] || country.iter().all(|b| b.is_ascii_alphabetic()))
+    }
+
+    fn parse_optional_blocks(bytes: &[u8]) -> Vec<OptionalBlock> {
+        let mut blocks = vec![];
+        let mut pos = 0;
+        while pos < bytes.len() && bytes[pos] != 0 {
+            let len = bytes[pos] as usize;
+            if len < 4 || pos + len > bytes.len() {
+                warn!(
+                    "Optional block at byte {} is truncated or malformed",
+                    PRIMARY_BLOCK_SIZE + pos
+                );
+                break;
+            }
+            let block = OptionalBlock {
+                raw: bytes[pos..pos + len].to_vec(),
+            };
+            if !block.is_valid() {
+                warn!("Optional block with id {} has a wrong checksum", block.id());
+            }
+            blocks.push(block);
+            pos += len;
+        }
+        blocks
+    }
+
+    fn strip0s(bytes: &[u8]) -> &[u8] {
+        let mut new_len = bytes.len();
+        for i in 0..(bytes.len() - 1) {
+            if bytes[bytes.len() - 1 - i] == 0x00 {
+                new_len = bytes.len() - 1 - i;
+            } else {
+                break;
+            }
+        }
+        &bytes[0..new_len]
+    }
+
+    pub fn calc_crc(bytes: &[u8]) -> u16 {
+        let crc_poly = 0x1021;
+        let mut crc_sum: u16 = 0xffff;
+
+        for b in bytes {
+            let mut c = *b as u16;
+            c <<= 8;
+            for _ in 0..8 {
+                let xor_flag = ((crc_sum ^ c) & 0x8000) != 0;
+                crc_sum <<= 1;
+                if xor_flag {
+                    crc_sum ^= crc_poly;
+                }
+                c <<= 1;
+            }
+            crc_sum &= 0xffff;
+        }
+        crc_sum
+    }
+
+    pub fn set_usage_type(&mut self, usage_type: u8) -> Result<(), ItemError> {
+        if usage_type > 15 {
+            return Err(ItemError::NibbleOutOfRange("usage_type"));
+        }
+        self.usage_type = usage_type;
+        Ok(())
+    }
+
+    pub fn set_number_of_parts(&mut self, number_of_parts: u8) {
+        self.number_of_parts = number_of_parts;
+    }
+
+    pub fn set_ordinal_number(&mut self, ordinal_number: u8) {
+        self.ordinal_number = ordinal_number;
+    }
+
+    pub fn set_item_id(&mut self, item_id: &str) -> Result<(), ItemError> {
+        if item_id.len() > 16 {
+            return Err(ItemError::TooLong {
+                field: "item_id",
+                max: 16,
+            });
+        }
+        self.item_id = item_id.to_string();
+        Ok(())
+    }
+
+    pub fn set_country(&mut self, country: &str) -> Result<(), ItemError> {
+        if country.len() > 2 {
+            return Err(ItemError::TooLong {
+                field: "country",
+                max: 2,
+            });
+        }
+        self.country = country.to_string();
+        Ok(())
+    }
+
+    pub fn set_library_id(&mut self, library_id: &str) -> Result<(), ItemError> {
+        if library_id.len() > 9 {
+            return Err(ItemError::TooLong {
+                field: "library_id",
+                max: 9,
+            });
+        }
+        self.library_id = library_id.to_string();
+        Ok(())
+    }
+
+    // Tag blocks covering the primary block
+    pub fn primary_blocks(block_size: usize) -> Range<u8> {
+        0..PRIMARY_BLOCK_SIZE.div_ceil(block_size) as u8
+    }
+
+    pub fn set_optional_blocks(&mut self, optional_blocks: Vec<OptionalBlock>) {
+        self.optional_blocks = optional_blocks;
+    }
+    pub fn optional_blocks(&self) -> &[OptionalBlock] {
+        &self.optional_blocks
+    }
+
+    pub fn set_card_id(&mut self, card_id: Vec<u8>) {
+        self.card_id = card_id;
+    }
+    pub fn set_card_id_string(&mut self, card_id: &str) -> Result<(), ItemError> {
+        self.card_id = from_hex_string(card_id)?;
+        Ok(())
+    }
+    pub fn card_id(&self) -> &[u8] {
+        &self.card_id
+    }
+}
+
+impl TagDataModel for DanishRfidItem {
+    fn format(&self) -> i16 {
+        DANISH_FORMAT
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PRIMARY_BLOCK_SIZE);
+        bytes.push((self.usage_type << 4) + self.standart_version);
+        bytes.push(self.number_of_parts);
+        bytes.push(self.ordinal_number);
+        for b in self.item_id.as_bytes() {
+            bytes.push(*b);
+        }
+        bytes.resize(3 + 16, 0x0);
+        for b in self.country.as_bytes() {
+            bytes.push(*b);
+        }
+        // The library id starts at its place even without the country
+        bytes.resize(3 + 16 + 2, 0x0);
+        for b in self.library_id.as_bytes() {
+            bytes.push(*b);
+        }
+        bytes.resize(PRIMARY_BLOCK_SIZE - 2, 0x0);
+        bytes.push(0x0);
+        bytes.push(0x0);
+
+        let crc = Self::calc_crc(&bytes).to_be_bytes();
+        bytes.truncate(PRIMARY_BLOCK_SIZE - 2);
+        bytes.insert(19, crc[1]);
+        bytes.insert(20, crc[0]);
+
+        // Optional blocks are followed by a terminating zero byte
+        if !self.optional_blocks.is_empty() {
+            for block in &self.optional_blocks {
+                bytes.extend_from_slice(block.as_bytes());
+            }
+            bytes.push(0x0);
+        }
+
+        bytes
+    }
+    fn item_id(&self) -> Option<&str> {
+        Some(&self.item_id)
+    }
+    fn library_id(&self) -> Option<String> {
+        Some(format!("{}-{}", self.country, self.library_id))
+    }
+    fn usage_type(&self) -> u8 {
+        self.usage_type
+    }
+    fn set_information(&self) -> (u8, u8) {
+        (self.ordinal_number, self.number_of_parts)
+    }
+}
+
+// Primary block of item 2935000003649 of library RU-293, which is 29 bytes long on tags
+// with a smaller memory. The CRC 0x9387 is stored LSB first
+#[cfg(test)]
+const SAMPLE: [u8; 29] = [
+    0x81, 0x01, 0x01, 0x32, 0x39, 0x33, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x33, 0x36, 0x34, 0x39,
+    0x00, 0x00, 0x00, 0x87, 0x93, 0x52, 0x55, 0x32, 0x39, 0x33, 0x00, 0x00, 0x00,
+];
+
+#[cfg(test)]
+fn sample_item() -> DanishRfidItem {
+    DanishRfidItem {
+        card_id: vec![],
+        usage_type: 8,
+        standart_version: 1,
+        number_of_parts: 1,
+        ordinal_number: 1,
+        item_id: "2935000003649".to_string(),
+        country: "RU".to_string(),
+        library_id: "293".to_string(),
+        optional_blocks: vec![],
+    }
+}
+
+#[test]
+fn bytes_to_item() {
+    let item = DanishRfidItem::from_bytes(&SAMPLE).unwrap();
+    assert_eq!(item, sample_item());
+    assert_eq!(item.item_id(), Some("2935000003649"));
+    assert_eq!(item.library_id(), Some("RU-293".to_string()));
+    assert_eq!(item.usage_type(), 8);
+    assert_eq!(item.set_information(), (1, 1));
+}
+
+#[test]
+fn item_to_bytes() {
+    let bytes = sample_item().to_bytes();
+    assert_eq!(bytes.len(), PRIMARY_BLOCK_SIZE);
+    assert_eq!(bytes[..SAMPLE.len()], SAMPLE);
+    assert_eq!(bytes[SAMPLE.len()..], [0x00; 3]);
+}
+
+#[test]
+fn crc() {
+    // The primary block with zeros in place of the CRC, which is moved to the end
+    let mut bytes_without_crc = SAMPLE.to_vec();
+    bytes_without_crc.drain(19..21);
+    bytes_without_crc.resize(PRIMARY_BLOCK_SIZE, 0x00);
+    assert_eq!(DanishRfidItem::calc_crc(&bytes_without_crc), 0x9387);
+    // Check value of the data model
+    assert_eq!(DanishRfidItem::calc_crc(b"RFID tag data model"), 0x1AEE);
+}
+
+#[test]
+fn optional_blocks_are_preserved() {
+    let mut item = DanishRfidItem::default();
+    item.set_item_id("2935000003649").unwrap();
+    item.set_country("RU").unwrap();
+    item.set_library_id("293").unwrap();
+    item.set_optional_blocks(vec![
+        OptionalBlock::new(0x01, b"SUPPLIER-42").unwrap(),
+        OptionalBlock::new(0x7F, &[0xDE, 0xAD]).unwrap(),
+    ]);
+
+    let bytes = item.to_bytes();
+    let decoded = DanishRfidItem::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.item_id(), Some("2935000003649"));
+    assert_eq!(decoded.optional_blocks(), item.optional_blocks());
+    assert!(decoded.optional_blocks().iter().all(|b| b.is_valid()));
+    assert_eq!(decoded.to_bytes(), bytes);
+}
+
+#[test]
+fn invalid_fields() {
+    let mut item = DanishRfidItem::default();
+    assert_eq!(
+        item.set_item_id("12345678901234567"),
+        Err(ItemError::TooLong {
+            field: "item_id",
+            max: 16
+        })
+    );
+    // The library id fills the primary block up to its end
+    assert_eq!(
+        item.set_library_id("1234567890"),
+        Err(ItemError::TooLong {
+            field: "library_id",
+            max: 9
+        })
+    );
+    assert!(item.set_library_id("123456789").is_ok());
+    assert_eq!(item.to_bytes().len(), PRIMARY_BLOCK_SIZE);
+    assert_eq!(
+        item.set_usage_type(16),
+        Err(ItemError::NibbleOutOfRange("usage_type"))
+    );
+    assert_eq!(item.set_card_id_string("E0XX"), Err(ItemError::NonHexUid));
+
+    let mut bytes = item.to_bytes();
+    bytes[19] ^= 0xFF;
+    assert_eq!(
+        DanishRfidItem::from_bytes(&bytes).unwrap_err(),
+        ItemError::BadCrc
+    );
+    // Without the layout of the model it's just other data
+    bytes[0] = 0x06;
+    assert_eq!(
+        DanishRfidItem::from_bytes(&bytes).unwrap_err(),
+        ItemError::InvalidField("primary block")
+    );
+}
@@ -0,0 +1,521 @@
+// ISO 28560-2 data model. Data elements are encoded according to ISO 15962
+// without a directory: every element starts with a precursor byte
+// (offset flag, compaction scheme, relative OID), followed by an optional offset byte,
+// a length byte and the compacted data. A zero precursor ends the data.
+use super::{ItemError, TagDataModel, ISO28560_2_FORMAT};
+use log::*;
+
+// Data storage format identifiers which mark ISO 28560-2 tags
+pub const DSFIDS: [u8; 2] = [0x06, 0x3E];
+
+// ISIL is at most 16 characters, see ISO 15511
+const MAX_ISIL_LEN: usize = 16;
+
+// Relative OIDs of the data elements
+const OID_PRIMARY_ITEM_ID: u8 = 1;
+const OID_OWNER_INSTITUTION: u8 = 3;
+const OID_SET_INFORMATION: u8 = 4;
+const OID_TYPE_OF_USAGE: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compaction {
+    ApplicationDefined = 0,
+    Integer = 1,
+    Numeric = 2,
+    FiveBit = 3,
+    SixBit = 4,
+    SevenBit = 5,
+    Octet = 6,
+    Utf8 = 7,
+}
+
+impl From<u8> for Compaction {
+    fn from(b: u8) -> Self {
+        match b & 0x07 {
+            1 => Self::Integer,
+            2 => Self::Numeric,
+            3 => Self::FiveBit,
+            4 => Self::SixBit,
+            5 => Self::SevenBit,
+            6 => Self::Octet,
+            7 => Self::Utf8,
+            _ => Self::ApplicationDefined,
+        }
+    }
+}
+
+// Data element which isn't decoded. Kept as is to be written back
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataElement {
+    oid: u8,
+    compaction: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct Iso28560_2Item {
+    primary_item_id: String,
+    owner_institution: Option<String>, // ISIL, e.g. DK-710100
+    set_information: Option<(u8, u8)>, // (ordinal number, number of parts)
+    type_of_usage: Option<u8>,
+    other_elements: Vec<DataElement>,
+}
+
+#[allow(dead_code)]
+impl Iso28560_2Item {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ItemError> {
+        let mut item = Self::default();
+        let mut primary_item_id = None;
+        let mut pos = 0;
+
+        while pos < bytes.len() && bytes[pos] != 0 {
+            let precursor = bytes[pos];
+            pos += 1;
+            let offset_flag = precursor & 0x80 != 0;
+            let compaction = (precursor >> 4) & 0x07;
+            let mut oid = precursor & 0x0F;
+            if oid == 0x0F {
+                oid = oid.saturating_add(*bytes.get(pos).ok_or(ItemError::TooShort)?);
+                pos += 1;
+            }
+            let mut offset = 0;
+            if offset_flag {
+                offset = *bytes.get(pos).ok_or(ItemError::TooShort)? as usize;
+                pos += 1;
+            }
+            let len = *bytes.get(pos).ok_or(ItemError::TooShort)? as usize;
+            pos += 1;
+            if pos + len > bytes.len() {
+                return Err(ItemError::TooShort);
+            }
+            let data = &bytes[pos..pos + len];
+            pos += len + offset;
+
+            // The primary item identifier must be the first element
+            if primary_item_id.is_none() && oid != OID_PRIMARY_ITEM_ID {
+                return Err(ItemError::InvalidField("primary item identifier"));
+            }
+
+            let compaction_scheme = Compaction::from(compaction);
+            match oid {
+                OID_PRIMARY_ITEM_ID => {
+                    primary_item_id = Some(decompact(compaction_scheme, data)?);
+                }
+                OID_OWNER_INSTITUTION => {
+                    item.owner_institution = Some(decompact(compaction_scheme, data)?);
+                }
+                OID_SET_INFORMATION => {
+                    let set = decompact(compaction_scheme, data)?;
+                    item.set_information = Some(parse_set_information(&set)?);
+                }
+                OID_TYPE_OF_USAGE => {
+                    item.type_of_usage = Some(decode_type_of_usage(compaction_scheme, data)?);
+                }
+                _ => {
+                    debug!("Keeping undecoded data element with relative OID {oid}");
+                    item.other_elements.push(DataElement {
+                        oid,
+                        compaction,
+                        data: data.to_vec(),
+                    });
+                }
+            }
+        }
+
+        item.primary_item_id = primary_item_id.ok_or(ItemError::TooShort)?;
+        Ok(item)
+    }
+
+    pub fn set_primary_item_id(&mut self, item_id: &str) -> Result<(), ItemError> {
+        if item_id.is_empty() {
+            return Err(ItemError::InvalidField("primary item identifier"));
+        }
+        if item_id.len() > u8::MAX as usize {
+            return Err(ItemError::TooLong {
+                field: "primary item identifier",
+                max: u8::MAX as usize,
+            });
+        }
+        self.primary_item_id = item_id.to_string();
+        Ok(())
+    }
+    pub fn primary_item_id(&self) -> &String {
+        &self.primary_item_id
+    }
+
+    pub fn set_owner_institution(&mut self, isil: Option<&str>) -> Result<(), ItemError> {
+        if isil.is_some_and(|s| s.chars().count() > MAX_ISIL_LEN) {
+            return Err(ItemError::TooLong {
+                field: "owner institution",
+                max: MAX_ISIL_LEN,
+            });
+        }
+        self.owner_institution = isil.map(|s| s.to_string());
+        Ok(())
+    }
+    pub fn owner_institution(&self) -> Option<&String> {
+        self.owner_institution.as_ref()
+    }
+
+    pub fn set_set_information(&mut self, set_information: Option<(u8, u8)>) {
+        self.set_information = set_information;
+    }
+    pub fn set_information(&self) -> Option<(u8, u8)> {
+        self.set_information
+    }
+
+    pub fn set_type_of_usage(&mut self, type_of_usage: Option<u8>) {
+        self.type_of_usage = type_of_usage;
+    }
+    pub fn type_of_usage(&self) -> Option<u8> {
+        self.type_of_usage
+    }
+
+    // Fails if a data element doesn't fit its length byte
+    pub fn encode(&self) -> Result<Vec<u8>, ItemError> {
+        let mut bytes = Vec::with_capacity(32);
+        push_element(&mut bytes, OID_PRIMARY_ITEM_ID, &self.primary_item_id)?;
+        if let Some((ordinal, parts)) = self.set_information {
+            let digits = if parts > 99 {
+                3
+            } else if parts > 9 {
+                2
+            } else {
+                1
+            };
+            let set = format!("{ordinal:0digits$}{parts:0digits$}");
+            push_element(&mut bytes, OID_SET_INFORMATION, &set)?;
+        }
+        if let Some(usage) = self.type_of_usage {
+            push_compacted(
+                &mut bytes,
+                OID_TYPE_OF_USAGE,
+                Compaction::Octet as u8,
+                &[usage],
+            )?;
+        }
+        if let Some(isil) = &self.owner_institution {
+            push_element(&mut bytes, OID_OWNER_INSTITUTION, isil)?;
+        }
+        for element in &self.other_elements {
+            push_compacted(&mut bytes, element.oid, element.compaction, &element.data)?;
+        }
+        bytes.push(0x00);
+        Ok(bytes)
+    }
+}
+
+impl TagDataModel for Iso28560_2Item {
+    fn format(&self) -> i16 {
+        ISO28560_2_FORMAT
+    }
+    // The setters keep every element within 255 bytes, and elements which are read
+    // from a tag can't be longer, so encoding doesn't fail
+    fn to_bytes(&self) -> Vec<u8> {
+        self.encode().unwrap_or_else(|e| {
+            error!("Unable to encode an ISO 28560-2 item: {e}");
+            vec![0x00]
+        })
+    }
+    fn item_id(&self) -> Option<&str> {
+        Some(&self.primary_item_id)
+    }
+    fn library_id(&self) -> Option<String> {
+        self.owner_institution.clone()
+    }
+    fn usage_type(&self) -> u8 {
+        self.type_of_usage.unwrap_or(0)
+    }
+    fn set_information(&self) -> (u8, u8) {
+        self.set_information.unwrap_or((1, 1))
+    }
+}
+
+// Set information is a numeric string: the first half is the ordinal number,
+// the second half is the total number of parts
+fn parse_set_information(set: &str) -> Result<(u8, u8), ItemError> {
+    if set.is_empty() || !set.len().is_multiple_of(2) {
+        return Err(ItemError::InvalidField("set information"));
+    }
+    let (ordinal, parts) = set.split_at(set.len() / 2);
+    match (ordinal.parse(), parts.parse()) {
+        (Ok(o), Ok(p)) => Ok((o, p)),
+        _ => Err(ItemError::InvalidField("set information")),
+    }
+}
+
+// Type of usage is one byte. Integer and octet compaction keep its value,
+// string schemes keep it as hexadecimal digits
+fn decode_type_of_usage(compaction: Compaction, data: &[u8]) -> Result<u8, ItemError> {
+    let invalid = ItemError::InvalidField("type of usage");
+    match compaction {
+        Compaction::Integer | Compaction::Octet | Compaction::ApplicationDefined => {
+            // Leading zero bytes don't change the value
+            let value: Vec<u8> = data.iter().copied().skip_while(|b| *b == 0).collect();
+            match value[..] {
+                [] => Ok(0),
+                [usage] => Ok(usage),
+                _ => Err(invalid),
+            }
+        }
+        _ => u8::from_str_radix(&decompact(compaction, data)?, 16).map_err(|_| invalid),
+    }
+}
+
+fn push_element(bytes: &mut Vec<u8>, oid: u8, value: &str) -> Result<(), ItemError> {
+    let (compaction, data) = compact(value);
+    push_compacted(bytes, oid, compaction as u8, &data)
+}
+
+fn push_compacted(
+    bytes: &mut Vec<u8>,
+    oid: u8,
+    compaction: u8,
+    data: &[u8],
+) -> Result<(), ItemError> {
+    let len = u8::try_from(data.len()).map_err(|_| ItemError::TooLong {
+        field: "data element",
+        max: u8::MAX as usize,
+    })?;
+    let oid_bits = oid.min(0x0F);
+    bytes.push((compaction << 4) | oid_bits);
+    if oid_bits == 0x0F {
+        bytes.push(oid - 0x0F);
+    }
+    bytes.push(len);
+    bytes.extend_from_slice(data);
+    Ok(())
+}
+
+// Chooses the most compact scheme for a string
+fn compact(value: &str) -> (Compaction, Vec<u8>) {
+    let is_numeric = !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+    if is_numeric && !value.starts_with('0') && value.len() <= 19 {
+        let int: u64 = value.parse().unwrap();
+        let bytes = int.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+        return (Compaction::Integer, bytes[skip..].to_vec());
+    }
+    if is_numeric {
+        let mut data = Vec::with_capacity(value.len() / 2 + 1);
+        for pair in value.as_bytes().chunks(2) {
+            let high = pair[0] - b'0';
+            let low = pair.get(1).map(|b| b - b'0').unwrap_or(0x0F);
+            data.push((high << 4) | low);
+        }
+        return (Compaction::Numeric, data);
+    }
+    if value.bytes().all(|b| (0x41..=0x5F).contains(&b)) {
+        return (Compaction::FiveBit, pack_bits(value.as_bytes(), 5));
+    }
+    // A trailing space could be taken for the padding, see unpack_bits()
+    if value.bytes().all(|b| (0x20..=0x5F).contains(&b)) && !value.ends_with(' ') {
+        return (Compaction::SixBit, pack_bits(value.as_bytes(), 6));
+    }
+    if value.is_ascii() {
+        return (Compaction::SevenBit, pack_bits(value.as_bytes(), 7));
+    }
+    (Compaction::Utf8, value.as_bytes().to_vec())
+}
+
+fn decompact(compaction: Compaction, data: &[u8]) -> Result<String, ItemError> {
+    match compaction {
+        Compaction::Integer => {
+            if data.len() > 8 {
+                return Err(ItemError::TooLong {
+                    field: "integer data element",
+                    max: 8,
+                });
+            }
+            Ok(data
+                .iter()
+                .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+                .to_string())
+        }
+        Compaction::Numeric => {
+            let mut s = String::with_capacity(data.len() * 2);
+            for b in data {
+                for nibble in [b >> 4, b & 0x0F] {
+                    match nibble {
+                        0..=9 => s.push((b'0' + nibble) as char),
+                        0x0F => (),
+                        _ => return Err(ItemError::InvalidField("numeric data element")),
+                    }
+                }
+            }
+            Ok(s)
+        }
+        Compaction::FiveBit => Ok(unpack_bits(data, 5)
+            .into_iter()
+            .map(|c| (c | 0x40) as char)
+            .collect()),
+        Compaction::SixBit => Ok(unpack_bits(data, 6)
+            .into_iter()
+            .map(|c| if c < 0x20 { c | 0x40 } else { c } as char)
+            .collect()),
+        Compaction::SevenBit => Ok(unpack_bits(data, 7)
+            .into_iter()
+            .map(|c| c as char)
+            .collect()),
+        Compaction::Octet | Compaction::ApplicationDefined => {
+            Ok(data.iter().map(|b| format!("{:02X}", b)).collect())
+        }
+        Compaction::Utf8 => String::from_utf8(data.to_vec())
+            .map_err(|_| ItemError::InvalidUtf8("UTF-8 data element")),
+    }
+}
+
+// Character whose leading bits pad the last byte of packed data (ISO 15962):
+// space (100000) in 6-bit compaction and zero bits otherwise. Zero isn't
+// a character of 5-bit and 7-bit compaction, but it's '@' in 6-bit compaction
+fn padding(width: u32) -> u8 {
+    match width {
+        6 => 0x20,
+        _ => 0x00,
+    }
+}
+
+// Packs the lowest `width` bits of every byte. The last byte is padded, see padding()
+fn pack_bits(values: &[u8], width: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(values.len() * width as usize / 8 + 1);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for v in values {
+        acc = (acc << width) | (*v as u32 & ((1 << width) - 1));
+        bits += width;
+        while bits >= 8 {
+            bits -= 8;
+            data.push((acc >> bits) as u8);
+        }
+    }
+    if bits > 0 {
+        let free = 8 - bits;
+        let pad = padding(width) as u32 >> width.saturating_sub(free);
+        data.push(((acc << free) | pad) as u8);
+    }
+    data
+}
+
+fn unpack_bits(data: &[u8], width: u32) -> Vec<u8> {
+    let mut values = Vec::with_capacity(data.len() * 8 / width as usize);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for b in data {
+        acc = (acc << 8) | *b as u32;
+        bits += 8;
+        while bits >= width {
+            bits -= width;
+            values.push(((acc >> bits) & ((1 << width) - 1)) as u8);
+        }
+    }
+    // The padding may be long enough to look like one more character.
+    // It's the padding character which lies in the last byte only
+    if values.len() > 1
+        && values.last() == Some(&padding(width))
+        && (values.len() - 1) * width as usize > (data.len() - 1) * 8
+    {
+        values.pop();
+    }
+    values
+}
+
+#[test]
+fn iso28560_2_round_trip() {
+    let mut item = Iso28560_2Item::default();
+    item.set_primary_item_id("2935000003649").unwrap();
+    item.set_owner_institution(Some("RU-293")).unwrap();
+    item.set_set_information(Some((1, 2)));
+    item.set_type_of_usage(Some(0x10));
+
+    let bytes = item.to_bytes();
+    let decoded = Iso28560_2Item::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.primary_item_id(), "2935000003649");
+    assert_eq!(decoded.owner_institution().unwrap(), "RU-293");
+    assert_eq!(decoded.set_information(), Some((1, 2)));
+    assert_eq!(decoded.type_of_usage(), Some(0x10));
+
+    for id in ["0012345", "ABCDE", "AB-12 X", "ab12"] {
+        item.set_primary_item_id(id).unwrap();
+        let decoded = Iso28560_2Item::from_bytes(&item.to_bytes()).unwrap();
+        assert_eq!(decoded.primary_item_id(), id);
+    }
+}
+
+#[test]
+fn iso28560_2_lengths() {
+    let mut item = Iso28560_2Item::default();
+    item.set_primary_item_id("2935000003649").unwrap();
+    item.set_owner_institution(Some("DK-1234567890123"))
+        .unwrap();
+    assert!(matches!(
+        item.set_owner_institution(Some("DK-12345678901234")),
+        Err(ItemError::TooLong {
+            field: "owner institution",
+            max: 16
+        })
+    ));
+    assert_eq!(item.owner_institution().unwrap(), "DK-1234567890123");
+    assert!(item.set_primary_item_id(&"A".repeat(256)).is_err());
+
+    // An element must fit its length byte
+    item.other_elements.push(DataElement {
+        oid: 20,
+        compaction: Compaction::Octet as u8,
+        data: vec![0xAA; 256],
+    });
+    assert!(matches!(
+        item.encode(),
+        Err(ItemError::TooLong {
+            field: "data element",
+            max: 255
+        })
+    ));
+    item.other_elements[0].data.pop();
+    let decoded = Iso28560_2Item::from_bytes(&item.encode().unwrap()).unwrap();
+    assert_eq!(decoded.other_elements, item.other_elements);
+}
+
+#[test]
+fn iso28560_2_type_of_usage() {
+    let mut item = Iso28560_2Item::default();
+    item.set_primary_item_id("2935000003649").unwrap();
+    for usage in [0x00, 0x0A, 0x10, 0x21, 0xFF] {
+        item.set_type_of_usage(Some(usage));
+        let decoded = Iso28560_2Item::from_bytes(&item.to_bytes()).unwrap();
+        assert_eq!(decoded.type_of_usage(), Some(usage));
+    }
+
+    // Other encoders may choose any scheme
+    let decode = |compaction: Compaction, data: &[u8]| {
+        let mut bytes = vec![];
+        push_element(&mut bytes, OID_PRIMARY_ITEM_ID, "1").unwrap();
+        push_compacted(&mut bytes, OID_TYPE_OF_USAGE, compaction as u8, data).unwrap();
+        bytes.push(0x00);
+        Iso28560_2Item::from_bytes(&bytes).map(|item| item.type_of_usage())
+    };
+    // 0x10 isn't taken for the digits of "16"
+    assert_eq!(decode(Compaction::Integer, &[0x10]), Ok(Some(0x10)));
+    assert_eq!(decode(Compaction::Integer, &[0x00, 0x21]), Ok(Some(0x21)));
+    assert_eq!(decode(Compaction::Numeric, &[0x21]), Ok(Some(0x21)));
+    assert_eq!(decode(Compaction::Octet, &[0x0A]), Ok(Some(0x0A)));
+    assert!(decode(Compaction::Integer, &[0x01, 0x00]).is_err());
+}
+
+#[test]
+fn iso28560_2_padding() {
+    // '@' is zero in 6-bit compaction, so it isn't taken for the padding
+    for id in ["AB@", "A@", "ABC@@@@", "@", "1-2@", "ABCDEFG@"] {
+        let (compaction, data) = compact(id);
+        assert_eq!(compaction, Compaction::SixBit, "{id}");
+        assert_eq!(decompact(compaction, &data).unwrap(), id);
+    }
+    // 5-bit and 7-bit characters are never zero
+    for id in ["ABC", "ABCDEFGHIJKLM", "abc", "abcdefg"] {
+        let (compaction, data) = compact(id);
+        assert_eq!(decompact(compaction, &data).unwrap(), id);
+    }
+    // Trailing spaces aren't packed by 6 bits
+    assert_eq!(compact("AB C ").0, Compaction::SevenBit);
+    let (compaction, data) = compact("AB C ");
+    assert_eq!(decompact(compaction, &data).unwrap(), "AB C ");
+}
@@ -1,12 +1,25 @@
+// Rocket doesn't take queries into account when it checks routes for collisions,
+// so routes which differ only by `action` have distinct ranks
+pub mod access;
+pub mod events;
 pub mod get_devices_list;
 pub mod get_items_list;
+pub mod get_tag_info;
+pub mod get_tags_security;
 pub mod index;
+pub mod permissions;
+pub mod raw_blocks;
 pub mod rfid_index;
+pub mod set_tags_security;
+pub mod websocket;
 pub mod write_tags;
 
+pub use self::access::{check_access, check_local_access, check_write_access, Access};
+use crate::config::Config;
 use log::*;
 use rocket::http::{ContentType, Header};
-use rocket_client_addr::ClientAddr;
+use rocket::serde::json;
+use write_tags::WriteError;
 
 // Wrapper for a response with a http status
 #[derive(Responder)]
@@ -19,10 +32,28 @@ pub enum RfidStatusResponse {
     Err403(RfidResponse),
     #[response(status = 404)]
     Err404(RfidResponse),
+    #[response(status = 500)]
+    Err500(RfidResponse),
+    #[response(status = 501)]
+    Err501(RfidResponse),
+}
+
+impl RfidStatusResponse {
+    // Http status and contents of a response, for the WebSocket API
+    pub fn into_parts(self) -> (u16, String) {
+        match self {
+            Self::Ok(r) => (200, r.inner),
+            Self::Err400(r) => (400, r.inner),
+            Self::Err403(r) => (403, r.inner),
+            Self::Err404(r) => (404, r.inner),
+            Self::Err500(r) => (500, r.inner),
+            Self::Err501(r) => (501, r.inner),
+        }
+    }
 }
 
 // Response struct. Inner is the contnets of a response
-// The next fields are headers
+// The next fields are headers. CORS header is replaced by access::cors()
 #[derive(Responder)]
 pub struct RfidResponse {
     inner: String,
@@ -59,19 +90,39 @@ impl RfidResponse {
     }
 }
 
-// Checks if remote address is local
-fn check_if_addr_local(client_addr: &ClientAddr) -> Result<(), RfidStatusResponse> {
-    //todo ipv6 check
-    if let Some(addr) = client_addr.get_ipv4_string() {
-        if addr == "127.0.0.1" || addr == "0.0.0.0" || addr == "127.0.0.0" {
-            return Ok(());
-        }
+// Shows confirm dialog to a user if it's enabled in the config
+fn confirm_by_user(config: &Config, text: &str) -> bool {
+    if !config.ask_when_writing() {
+        return true;
     }
-    warn!(
-        "Remote address {} isn't local. Interrupting...",
-        client_addr
-            .get_ipv4_string()
-            .unwrap_or("{unknown}".to_string())
-    );
-    Err(RfidStatusResponse::Err403(RfidResponse::default()))
+    let confirm = rocket::tokio::task::block_in_place(|| {
+        native_dialog::MessageDialog::new()
+            .set_title("RFID Server For Libraries")
+            .set_text(text)
+            .set_type(native_dialog::MessageType::Info)
+            .show_confirm()
+            .unwrap_or(true)
+    });
+    info!("User confirmation for \"{text}\": {confirm}");
+    confirm
+}
+
+// The driver of a device has panicked or its thread has stopped.
+// The error is also shown by getDevicesList
+fn device_failure(device_id: &str) -> RfidStatusResponse {
+    error!("Device {device_id} has failed to run a command");
+    let error = WriteError {
+        r#type: "Device Error".to_string(),
+        message: "Device has failed, see its state in getDevicesList".to_string(),
+    };
+    RfidStatusResponse::Err500(RfidResponse::from_string(json::to_string(&error).unwrap()))
+}
+
+// The reader can't do what is requested, see its features in getDevicesList
+fn not_supported(message: &str) -> RfidStatusResponse {
+    let error = WriteError {
+        r#type: "Not Supported".to_string(),
+        message: message.to_string(),
+    };
+    RfidStatusResponse::Err501(RfidResponse::from_string(json::to_string(&error).unwrap()))
 }
@@ -0,0 +1,335 @@
+// Which clients may use the server: local addresses and allowed networks, allowed origins (websites)
+// and API tokens from the config. Browsers send the origin of a website in the Origin header.
+// Tokens are sent in `Authorization: Bearer <token>` or in the `token` query parameter,
+// since EventSource and WebSocket in browsers can't set headers
+use super::{RfidResponse, RfidStatusResponse};
+use crate::config::Config;
+use crate::permissions::{Permission, Permissions};
+use log::*;
+use rocket::fairing::AdHoc;
+use rocket::http::Method;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+// Allows any origin in `allowed_origins`
+pub const ANY_ORIGIN: &str = "*";
+
+// Result of checking a request. It's made once and cached for the request
+#[derive(Debug, Clone)]
+pub struct Access {
+    // Value of Access-Control-Allow-Origin header. None if the origin isn't allowed
+    allow_origin: Option<String>,
+    // Why the request is forbidden
+    denied: Option<String>,
+    // Origin which isn't in the config. It's allowed by the user
+    paired: Option<(String, Permissions)>,
+    // Whether the client is on the computer itself
+    is_local: bool,
+}
+
+impl Access {
+    pub fn new(
+        config: &Config,
+        permissions: &Permissions,
+        remote: Option<IpAddr>,
+        origin: Option<&str>,
+        token: Option<&str>,
+        is_preflight: bool,
+    ) -> Self {
+        let origin_token = origin.and_then(|o| config.origin_token(o));
+        let is_configured =
+            |o: &str| origin_token.is_some() || config.allowed_origins().iter().any(|a| a == o);
+        // Any origin isn't allowed if the user decides
+        let any_origin =
+            !config.pair_origins() && config.allowed_origins().iter().any(|o| o == ANY_ORIGIN);
+        let paired = origin.filter(|o| config.pair_origins() && !is_configured(o));
+        let allow_origin = match origin {
+            _ if any_origin => Some(ANY_ORIGIN.to_string()),
+            Some(o) if is_configured(o) => Some(o.to_string()),
+            // The user is asked when the request itself comes
+            Some(o) if is_preflight && permissions.get(o) != Some(Permission::Deny) => {
+                paired.map(|o| o.to_string())
+            }
+            _ => None,
+        };
+        // An origin's own token is used instead of the shared one.
+        // Preflight requests of browsers never have credentials
+        let expected_token = origin_token.or(config.api_token());
+
+        let is_allowed = |ip: IpAddr| {
+            is_loopback(ip) || config.allowed_networks().iter().any(|n| n.contains(ip))
+        };
+        let denied = match remote {
+            None => Some("Remote address is unknown".to_string()),
+            Some(ip) if !is_allowed(ip) => Some(format!("Remote address {ip} isn't allowed")),
+            _ => match origin {
+                Some(o) if allow_origin.is_none() && (paired.is_none() || is_preflight) => {
+                    Some(format!("Origin {o} isn't allowed"))
+                }
+                _ if is_preflight => None,
+                _ => match expected_token {
+                    Some(expected) if token != Some(expected.as_str()) => Some(format!(
+                        "Wrong API token from origin {}",
+                        origin.unwrap_or("{none}")
+                    )),
+                    _ => None,
+                },
+            },
+        };
+        let paired = paired.filter(|_| denied.is_none() && !is_preflight);
+        Self {
+            allow_origin: allow_origin.filter(|_| denied.is_none()),
+            denied,
+            paired: paired.map(|o| (o.to_string(), permissions.clone())),
+            is_local: remote.is_some_and(is_loopback),
+        }
+    }
+
+    // Paired origins get the header after the user has allowed them
+    fn cors_origin(&self) -> Option<&String> {
+        match &self.paired {
+            Some((origin, permissions)) if permissions.get(origin) >= Some(Permission::Read) => {
+                Some(origin)
+            }
+            _ => self.allow_origin.as_ref(),
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check(&self, needed: Permission) -> Result<(), RfidStatusResponse> {
+        let reason = match (&self.denied, &self.paired) {
+            (Some(reason), _) => reason.clone(),
+            (None, Some((origin, permissions))) if !permissions.request(origin, needed) => {
+                format!(
+                    "Origin {origin} isn't allowed to {} cards by the user",
+                    needed.as_str()
+                )
+            }
+            _ => return Ok(()),
+        };
+        warn!("{reason}. Interrupting...");
+        Err(RfidStatusResponse::Err403(RfidResponse::default()))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Access {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let access = request
+            .local_cache_async(async {
+                // Headers of proxies aren't trusted, the server is used directly
+                let remote = request.remote().map(|addr| addr.ip());
+                let token = request
+                    .headers()
+                    .get_one("Authorization")
+                    .and_then(|h| h.strip_prefix("Bearer "))
+                    .or_else(|| request.query_value::<&str>("token").and_then(|t| t.ok()));
+                let is_preflight = request.method() == Method::Options
+                    && request.headers().contains("Access-Control-Request-Method");
+                let rocket = request.rocket();
+                match (rocket.state::<Config>(), rocket.state::<Permissions>()) {
+                    (Some(config), Some(permissions)) => Access::new(
+                        config,
+                        permissions,
+                        remote,
+                        request.headers().get_one("Origin"),
+                        token,
+                        is_preflight,
+                    ),
+                    _ => Access {
+                        allow_origin: None,
+                        denied: Some("Config isn't loaded".to_string()),
+                        paired: None,
+                        is_local: false,
+                    },
+                }
+            })
+            .await;
+        request::Outcome::Success(access)
+    }
+}
+
+// Requests are rejected by handlers, so the responses look like other responses of the API
+#[allow(clippy::result_large_err)]
+pub fn check_access(access: &Access) -> Result<(), RfidStatusResponse> {
+    access.check(Permission::Read)
+}
+
+// The same for requests which change tags
+#[allow(clippy::result_large_err)]
+pub fn check_write_access(access: &Access) -> Result<(), RfidStatusResponse> {
+    access.check(Permission::Write)
+}
+
+// Debugging actions are only for the computer itself, even if networks are allowed
+#[allow(clippy::result_large_err)]
+pub fn check_local_access(access: &Access) -> Result<(), RfidStatusResponse> {
+    if access.is_local {
+        return Ok(());
+    }
+    warn!("Debugging actions aren't allowed for remote clients. Interrupting...");
+    Err(RfidStatusResponse::Err403(RfidResponse::default()))
+}
+
+// Sets CORS headers of every response according to the origin of its request
+pub fn cors() -> AdHoc {
+    AdHoc::on_response("CORS", |request, response| {
+        Box::pin(async move {
+            let access = request.guard::<&Access>().await.succeeded();
+            match access.and_then(|a| a.cors_origin()) {
+                Some(origin) => {
+                    response.set_raw_header("Access-Control-Allow-Origin", origin.clone());
+                    if origin != ANY_ORIGIN {
+                        response.set_raw_header("Vary", "Origin");
+                    }
+                }
+                None => response.remove_header("Access-Control-Allow-Origin"),
+            }
+            if request.method() == Method::Options {
+                response.set_raw_header("Access-Control-Allow-Headers", "Authorization");
+            }
+        })
+    })
+}
+
+// Whether the address belongs to the computer itself
+pub fn is_loopback(ip: IpAddr) -> bool {
+    match canonical(ip) {
+        IpAddr::V4(ip) => ip.is_loopback(),
+        IpAddr::V6(ip) => ip.is_loopback(),
+    }
+}
+
+// IPv4 clients of IPv6 sockets have IPv4-mapped addresses, e.g. ::ffff:127.0.0.1
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+// Range of addresses in CIDR notation, e.g. 192.168.1.0/24 or fd00::/8.
+// An address without a prefix length is a range of one address
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", try_from = "String", into = "String")]
+pub struct Network {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ((network, bits), (ip, ip_bits)) = (to_bits(self.addr), to_bits(ip));
+        let shift = (bits - self.prefix_len) as u32;
+        bits == ip_bits && network.checked_shr(shift) == ip.checked_shr(shift)
+    }
+}
+
+// Address as a number and its length in bits
+fn to_bits(ip: IpAddr) -> (u128, u8) {
+    match canonical(ip) {
+        IpAddr::V4(ip) => (u32::from(ip) as u128, 32),
+        IpAddr::V6(ip) => (u128::from(ip), 128),
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let error = || format!("Network {s} must look like 192.168.1.0/24");
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse::<u8>().map_err(|_| error())?)),
+            None => (s, None),
+        };
+        let addr = canonical(addr.parse::<IpAddr>().map_err(|_| error())?);
+        let bits = to_bits(addr).1;
+        match prefix_len.unwrap_or(bits) {
+            len if len <= bits => Ok(Self {
+                addr,
+                prefix_len: len,
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl From<Network> for String {
+    fn from(network: Network) -> Self {
+        network.to_string()
+    }
+}
+
+#[test]
+fn loopback_addresses() {
+    for ip in [
+        "127.0.0.1",
+        "127.0.0.2",
+        "127.255.255.254",
+        "::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(is_loopback(ip.parse().unwrap()), "{ip}");
+    }
+    for ip in [
+        "0.0.0.0",
+        "10.0.0.1",
+        "128.0.0.1",
+        "::",
+        "::2",
+        "0.0.0.1",
+        "::ffff:10.0.0.1",
+    ] {
+        assert!(!is_loopback(ip.parse().unwrap()), "{ip}");
+    }
+}
+
+#[test]
+fn networks() {
+    let contains = |network: &str, ip: &str| {
+        Network::from_str(network)
+            .unwrap()
+            .contains(ip.parse().unwrap())
+    };
+    assert!(contains("192.168.1.0/24", "192.168.1.77"));
+    assert!(contains("192.168.1.0/24", "::ffff:192.168.1.77"));
+    assert!(!contains("192.168.1.0/24", "192.168.2.1"));
+    assert!(contains("10.1.2.3", "10.1.2.3"));
+    assert!(!contains("10.1.2.3", "10.1.2.4"));
+    assert!(contains("0.0.0.0/0", "203.0.113.5"));
+    assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+    assert!(contains("fd00::/8", "fd12:3456::1"));
+    assert!(!contains("fd00::/8", "fe80::1"));
+    assert!(contains("::/0", "2001:db8::1"));
+
+    for network in [
+        "192.168.1.0/33",
+        "fd00::/129",
+        "192.168.1/24",
+        "localhost",
+        "10.0.0.0/",
+    ] {
+        assert!(Network::from_str(network).is_err(), "{network}");
+    }
+    assert_eq!(
+        Network::from_str("10.0.0.1").unwrap().to_string(),
+        "10.0.0.1/32"
+    );
+}
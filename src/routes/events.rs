@@ -0,0 +1,50 @@
+use super::{check_access, Access, RfidResponse, RfidStatusResponse};
+use crate::events::EventsHub;
+use log::*;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Shutdown, State};
+
+// GET http://127.0.0.1:21646/rfid/events?deviceId=<deviceId>
+// Server-Sent Events: tagAdded, tagRemoved and tagChanged.
+// Data of each event is an item in the format of getItemsList
+#[allow(non_snake_case, clippy::result_large_err)]
+#[get("/events?<deviceId>")]
+pub fn handler(
+    events_hub: &State<EventsHub>,
+    access: &Access,
+    deviceId: &str,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], RfidStatusResponse> {
+    // Check if the client is allowed. If not then exit
+    check_access(access)?;
+
+    let (current, mut subscription) = match events_hub.subscribe(deviceId) {
+        Some(s) => s,
+        None => {
+            debug!("Wrong device");
+            return Err(RfidStatusResponse::Err404(RfidResponse::default()));
+        }
+    };
+    info!("New subscriber of {deviceId} events");
+
+    let stream = EventStream! {
+        for event in current {
+            yield Event::json(&event.item).event(event.kind.as_str());
+        }
+        loop {
+            let event = select! {
+                event = subscription.recv() => event,
+                _ = &mut shutdown => break,
+            };
+            match event {
+                Ok(event) => yield Event::json(&event.item).event(event.kind.as_str()),
+                Err(RecvError::Lagged(n)) => warn!("Subscriber missed {n} events"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+    // CORS headers are set by access::cors(), as for other responses
+    Ok(stream)
+}
@@ -1,9 +1,9 @@
-use super::{check_if_addr_local, RfidResponse, RfidStatusResponse};
-use crate::devices::DevicesList;
+use super::{check_access, Access, RfidResponse, RfidStatusResponse};
+use crate::devices::{DeviceState, DevicesList};
 use log::*;
 use rocket::serde::{json, Deserialize, Serialize};
 use rocket::State;
-use rocket_client_addr::ClientAddr;
+use std::time::UNIX_EPOCH;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "rocket::serde")]
@@ -17,37 +17,49 @@ pub struct DeviceJson {
     isError: bool,
     isReadOnly: bool,
     compoundDataIsSupported: bool,
+    securityIsSupported: bool,
+    lockIsSupported: bool,
+    tagInfoIsSupported: bool,
+    beepIsSupported: bool,
+    // Unix time in ms when the device has come online, gone offline or failed
+    stateChangedAt: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
 }
 
 // GET http://127.0.0.1:21646/rfid/?action=getDevicesList
 #[get("/?action=getDevicesList")]
-pub fn handler(
-    shared_resource: &State<DevicesList>,
-    client_addr: &ClientAddr,
-) -> RfidStatusResponse {
-    // Check if remote address is local. If not then exit
-    if let Err(r) = check_if_addr_local(client_addr) {
+pub fn handler(shared_resource: &State<DevicesList>, access: &Access) -> RfidStatusResponse {
+    // Check if the client is allowed. If not then exit
+    if let Err(r) = check_access(access) {
         return r;
     }
 
     let devices = shared_resource.inner().get();
     let mut devices_json = Vec::with_capacity(devices.len());
-    for (name, device_mutex) in devices {
-        let mut device = device_mutex.lock().unwrap();
-        device.connect();
-        if device.is_connected() {
-            info!("Available device: {name}");
-            devices_json.push(DeviceJson {
-                id: name.to_string(),
-                title: name.to_owned(),
-                isOnline: device.is_connected(),
-                manualConnectIsNeeded: false,
-                multiTagIsSupported: device.multi_tag_is_supported(),
-                isError: false,
-                isReadOnly: device.is_read_only(),
-                compoundDataIsSupported: device.compound_data_is_supported(),
-            });
-        }
+    for (name, device) in devices {
+        let status = device.status();
+        let features = device.features();
+        debug!("Device {name}: {:?}", status);
+        devices_json.push(DeviceJson {
+            id: name.to_string(),
+            title: device.title().to_string(),
+            isOnline: status.state == DeviceState::Connected,
+            manualConnectIsNeeded: false,
+            multiTagIsSupported: features.multi_tag_is_supported,
+            isError: status.state == DeviceState::Error,
+            isReadOnly: features.is_read_only,
+            compoundDataIsSupported: features.compound_data_is_supported,
+            securityIsSupported: features.security_is_supported,
+            lockIsSupported: features.lock_is_supported,
+            tagInfoIsSupported: features.tag_info_is_supported,
+            beepIsSupported: features.beep_is_supported,
+            stateChangedAt: status
+                .since
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            error: status.error,
+        });
     }
 
     let response = json::to_string(&devices_json).unwrap_or("[]".to_string());
@@ -59,7 +71,7 @@ pub fn handler(
 #[options("/?action=getDevicesList")]
 pub fn handler_options(
     shared_resource: &State<DevicesList>,
-    client_addr: &ClientAddr,
+    access: &Access,
 ) -> RfidStatusResponse {
-    handler(shared_resource, client_addr)
+    handler(shared_resource, access)
 }
@@ -1,11 +1,14 @@
-use super::{check_if_addr_local, RfidResponse, RfidStatusResponse};
-use crate::devices::DevicesList;
+use super::{check_access, Access, RfidResponse, RfidStatusResponse};
+use crate::{
+    devices::{DevicesList, RawTag},
+    rfid_items::{self, to_hex_string, TagStatus},
+};
 use log::*;
 use rocket::serde::{json, Deserialize, Serialize};
 use rocket::State;
-use rocket_client_addr::ClientAddr;
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(crate = "rocket::serde")]
 pub struct ItemResponse {
     id: Option<String>,
@@ -13,7 +16,7 @@ pub struct ItemResponse {
     tags: Vec<Tag>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(crate = "rocket::serde")]
 #[allow(non_snake_case)]
 pub struct Tag {
@@ -24,77 +27,97 @@ pub struct Tag {
     itemSize: u8,
     indexInItemPack: u8,
     libraryId: String,
+    status: String, // ok, blank, crcError or unknownFormat
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rawData: Option<String>, // user memory in hex
 }
 
-// GET http://127.0.0.1:21646/rfid/?action=getItemsList&deviceId=<deviceId>
+impl From<Tag> for ItemResponse {
+    fn from(tag: Tag) -> Self {
+        Self {
+            id: tag.itemId.to_owned(),
+            r#type: tag.r#type.to_owned(),
+            tags: vec![tag],
+        }
+    }
+}
+
+// Decodes a tag and makes its JSON representation
+pub fn tag_response(raw_tag: &RawTag, raw_data: bool) -> Tag {
+    let item = rfid_items::decode(raw_tag.dsfid, &raw_tag.memory);
+    let status = item.status();
+    if status != TagStatus::Ok {
+        info!(
+            "Tag {} isn't decoded: {}",
+            to_hex_string(&raw_tag.uid),
+            status.as_str()
+        );
+    }
+
+    let (ordinal_number, number_of_parts) = item.set_information();
+    Tag {
+        tagId: to_hex_string(&raw_tag.uid),
+        itemId: item.item_id().map(|s| s.to_owned()),
+        format: item.format(),
+        r#type: item.usage_type(),
+        itemSize: number_of_parts,
+        indexInItemPack: ordinal_number,
+        libraryId: item.library_id().unwrap_or_default(),
+        status: status.as_str().to_string(),
+        rawData: match raw_data {
+            true => Some(to_hex_string(&raw_tag.memory)),
+            false => None,
+        },
+    }
+}
+
+// GET http://127.0.0.1:21646/rfid/?action=getItemsList&deviceId=<deviceId>[&rawData=true][&maxAge=<ms>]
 #[allow(non_snake_case)]
-#[get("/?action=getItemsList&<deviceId>")]
-pub fn handler(
+#[get("/?action=getItemsList&<deviceId>&<rawData>&<maxAge>")]
+pub async fn handler(
     shared_resource: &State<DevicesList>,
-    client_addr: &ClientAddr,
+    access: &Access,
     deviceId: &str,
+    rawData: Option<bool>,
+    maxAge: Option<u64>,
 ) -> RfidStatusResponse {
-    // Check if remote address is local. If not then exit
-    if let Err(r) = check_if_addr_local(client_addr) {
+    // Check if the client is allowed. If not then exit
+    if let Err(r) = check_access(access) {
         return r;
     }
 
-    let get_device = shared_resource.inner().get().get(&deviceId.to_string());
+    let get_device = shared_resource.device(deviceId);
     if get_device.is_none() {
         debug!("Wrong device");
         return RfidStatusResponse::Err404(RfidResponse::default());
     }
 
-    let device_mutex = get_device.unwrap();
-    let mut device = device_mutex.lock().unwrap();
-    device.connect();
+    // By default tags are read right now, but clients polling the list may accept
+    // the result of a recent read instead of waiting for the reader
+    let max_age = Duration::from_millis(maxAge.unwrap_or(0));
+    let inventory = match get_device.unwrap().inventory(max_age).await {
+        Some(i) => i,
+        None => return RfidStatusResponse::Err404(RfidResponse::from_str("")),
+    };
 
-    if !device.is_connected() {
-        return RfidStatusResponse::Err404(RfidResponse::from_str(""));
-    }
-
-    let items = device.get_items();
-    if items.is_empty() {
+    let tags = &inventory.tags;
+    if tags.is_empty() {
         info!("No cards found");
         return RfidStatusResponse::Ok(RfidResponse::from_str("[]"));
     }
 
-    let mut item_responses = Vec::with_capacity(items.len());
-    for item in items {
-        if item.is_empty() {
-            item_responses.push(ItemResponse {
-                id: None,
-                r#type: 0,
-                tags: vec![Tag {
-                    tagId: "{}".to_string(),
-                    itemId: None,
-                    format: -1,
-                    r#type: 0,
-                    itemSize: 0,
-                    indexInItemPack: 0,
-                    libraryId: "".to_string(),
-                }],
-            });
-            continue;
-        }
-        let mut library_id = item.country().to_owned();
-        library_id.push('-');
-        library_id.push_str(item.library_id());
+    let mut item_responses: Vec<ItemResponse> = Vec::with_capacity(tags.len());
+    for raw_tag in tags {
+        let tag = tag_response(raw_tag, rawData == Some(true));
 
-        let tag = Tag {
-            tagId: item.card_id_string(),
-            itemId: Some(item.item_id().to_owned()),
-            format: 61,
-            r#type: item.usage_type(),
-            itemSize: item.number_of_parts(),
-            indexInItemPack: item.ordinal_number(),
-            libraryId: library_id,
-        };
-        item_responses.push(ItemResponse {
-            id: tag.itemId.to_owned(),
-            r#type: tag.r#type.to_owned(),
-            tags: vec![tag],
-        });
+        // Parts of the same item are grouped together
+        let same_item = item_responses
+            .iter_mut()
+            .find(|i| tag.itemId.is_some() && i.id == tag.itemId);
+        match same_item {
+            Some(item_response) => item_response.tags.push(tag),
+            None => item_responses.push(ItemResponse::from(tag)),
+        }
     }
 
     let response = json::to_string(&item_responses).unwrap();
@@ -103,13 +126,15 @@ pub fn handler(
     RfidStatusResponse::Ok(RfidResponse::from_string(response))
 }
 
-// OPTIONS http://127.0.0.1:21646/rfid/?action=getItemsList&deviceId=<deviceId>
-#[options("/?action=getItemsList&<deviceId>")]
+// OPTIONS http://127.0.0.1:21646/rfid/?action=getItemsList&deviceId=<deviceId>[&rawData=true][&maxAge=<ms>]
+#[options("/?action=getItemsList&<deviceId>&<rawData>&<maxAge>")]
 #[allow(non_snake_case)]
-pub fn handler_options(
+pub async fn handler_options(
     shared_resource: &State<DevicesList>,
-    client_addr: &ClientAddr,
+    access: &Access,
     deviceId: &str,
+    rawData: Option<bool>,
+    maxAge: Option<u64>,
 ) -> RfidStatusResponse {
-    handler(shared_resource, client_addr, deviceId)
+    handler(shared_resource, access, deviceId, rawData, maxAge).await
 }
@@ -0,0 +1,101 @@
+use super::{
+    check_access, device_failure, not_supported, Access, RfidResponse, RfidStatusResponse,
+};
+use crate::{devices::DevicesList, rfid_items::from_hex_string};
+use log::*;
+use rocket::serde::{json, Deserialize, Serialize};
+use rocket::State;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+#[allow(non_snake_case)]
+pub struct TagInfoResponse {
+    tagId: String,
+    dsfid: Option<u8>,
+    afi: Option<u8>,
+    blockSize: Option<u8>,
+    blockCount: Option<u16>,
+    icReference: Option<u8>,
+    manufacturer: Option<String>,
+}
+
+// GET http://127.0.0.1:21646/rfid/?action=getTagInfo&deviceId=<deviceId>&tagId=<tagId>
+#[allow(non_snake_case)]
+#[get("/?action=getTagInfo&<deviceId>&<tagId>", rank = 2)]
+pub async fn handler(
+    shared_resource: &State<DevicesList>,
+    access: &Access,
+    deviceId: &str,
+    tagId: &str,
+) -> RfidStatusResponse {
+    // Check if the client is allowed. If not then exit
+    if let Err(r) = check_access(access) {
+        return r;
+    }
+
+    let uid = match from_hex_string(tagId) {
+        Ok(uid) => uid,
+        Err(_) => {
+            debug!("Wrong tag id");
+            return RfidStatusResponse::Err400(RfidResponse::default());
+        }
+    };
+
+    let get_device = shared_resource.device(deviceId);
+    if get_device.is_none() {
+        debug!("Wrong device");
+        return RfidStatusResponse::Err404(RfidResponse::default());
+    }
+
+    let device = get_device.unwrap();
+    if !device.features().tag_info_is_supported {
+        debug!("Device {deviceId} doesn't know system information of tags");
+        return not_supported("Reader doesn't support reading system information of tags");
+    }
+
+    let info = device
+        .run(move |device| {
+            device.connect();
+            match device.is_connected() {
+                true => Some(device.get_tag_info(&uid)),
+                false => None,
+            }
+        })
+        .await;
+
+    let info = match info {
+        None => return device_failure(deviceId),
+        Some(None) => return RfidStatusResponse::Err404(RfidResponse::from_str("")),
+        Some(Some(Some(info))) => info,
+        Some(Some(None)) => {
+            info!("Tag {tagId} is not found");
+            return RfidStatusResponse::Err404(RfidResponse::default());
+        }
+    };
+
+    let response = json::to_string(&TagInfoResponse {
+        tagId: tagId.to_uppercase(),
+        dsfid: info.dsfid,
+        afi: info.afi,
+        blockSize: info.block_size,
+        blockCount: info.block_count,
+        icReference: info.ic_reference,
+        manufacturer: info.manufacturer().map(|m| m.to_string()),
+    })
+    .unwrap();
+    debug!("{response}");
+
+    RfidStatusResponse::Ok(RfidResponse::from_string(response))
+}
+
+// OPTIONS http://127.0.0.1:21646/rfid/?action=getTagInfo&deviceId=<deviceId>&tagId=<tagId>
+#[options("/?action=getTagInfo&<deviceId>&<tagId>", rank = 2)]
+#[allow(non_snake_case)]
+pub async fn handler_options(
+    shared_resource: &State<DevicesList>,
+    access: &Access,
+    deviceId: &str,
+    tagId: &str,
+) -> RfidStatusResponse {
+    handler(shared_resource, access, deviceId, tagId).await
+}
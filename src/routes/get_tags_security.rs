@@ -0,0 +1,75 @@
+use super::{check_access, device_failure, Access, RfidResponse, RfidStatusResponse};
+use crate::devices::{DevicesList, AFI_SECURED};
+use log::*;
+use rocket::serde::{json, Deserialize, Serialize};
+use rocket::State;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+#[allow(non_snake_case)]
+pub struct TagSecurityResponse {
+    tagId: String,
+    isSecuritySupported: bool,
+    isSecured: bool,
+}
+
+// GET http://127.0.0.1:21646/rfid/?action=getTagsSecurity&deviceId=<deviceId>
+#[allow(non_snake_case)]
+#[get("/?action=getTagsSecurity&<deviceId>", rank = 1)]
+pub async fn handler(
+    shared_resource: &State<DevicesList>,
+    access: &Access,
+    deviceId: &str,
+) -> RfidStatusResponse {
+    // Check if the client is allowed. If not then exit
+    if let Err(r) = check_access(access) {
+        return r;
+    }
+
+    let get_device = shared_resource.device(deviceId);
+    if get_device.is_none() {
+        debug!("Wrong device");
+        return RfidStatusResponse::Err404(RfidResponse::default());
+    }
+
+    let device = get_device.unwrap();
+    let tags_security = device
+        .run(|device| {
+            device.connect();
+            match device.is_connected() {
+                true => Some(device.get_tags_security()),
+                false => None,
+            }
+        })
+        .await;
+    let tags_security = match tags_security {
+        Some(Some(t)) => t,
+        Some(None) => return RfidStatusResponse::Err404(RfidResponse::from_str("")),
+        None => return device_failure(deviceId),
+    };
+
+    let tags: Vec<TagSecurityResponse> = tags_security
+        .into_iter()
+        .map(|tag| TagSecurityResponse {
+            tagId: tag.tag_id,
+            isSecuritySupported: tag.afi.is_some(),
+            isSecured: tag.afi == Some(AFI_SECURED),
+        })
+        .collect();
+
+    let response = json::to_string(&tags).unwrap();
+    debug!("{response}");
+
+    RfidStatusResponse::Ok(RfidResponse::from_string(response))
+}
+
+// OPTIONS http://127.0.0.1:21646/rfid/?action=getTagsSecurity&deviceId=<deviceId>
+#[options("/?action=getTagsSecurity&<deviceId>", rank = 1)]
+#[allow(non_snake_case)]
+pub async fn handler_options(
+    shared_resource: &State<DevicesList>,
+    access: &Access,
+    deviceId: &str,
+) -> RfidStatusResponse {
+    handler(shared_resource, access, deviceId).await
+}
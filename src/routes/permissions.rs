@@ -0,0 +1,160 @@
+use super::{access::is_loopback, RfidResponse, RfidStatusResponse};
+use crate::config::Config;
+use crate::permissions::{Permission, Permissions};
+use log::*;
+use rocket::form::Form;
+use rocket::http::Method;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::Redirect;
+use rocket::State;
+use std::str::FromStr;
+
+// The page is only for the user of the computer. Websites can't use it,
+// even allowed ones, so they can't change their own permissions
+pub struct Owner {
+    denied: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Owner {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let remote = request.remote().map(|addr| addr.ip());
+        // Another name of the computer could point to a website (DNS rebinding),
+        // so only its local names at the port of the server are accepted
+        let port = request
+            .rocket()
+            .state::<Config>()
+            .map(|config| config.port());
+        let host = request.headers().get_one("Host").filter(|host| {
+            port.is_some_and(|port| {
+                ["127.0.0.1", "localhost", "[::1]"]
+                    .iter()
+                    .any(|name| *host == format!("{name}:{port}"))
+            })
+        });
+        // Forms of the page itself have the origin of the server. Changes
+        // without the origin could be sent by any website
+        let own_origin = host.map(|host| format!("http://{host}"));
+        let origin = request.headers().get_one("Origin");
+        let denied = match (remote, host, origin) {
+            (Some(ip), _, _) if !is_loopback(ip) => {
+                Some(format!("Remote address {ip} isn't local"))
+            }
+            (None, _, _) => Some("Remote address is unknown".to_string()),
+            (_, None, _) => Some(format!(
+                "Host {} isn't the local server",
+                request.headers().get_one("Host").unwrap_or("(none)")
+            )),
+            (_, _, None) if request.method() == Method::Post => {
+                Some("Origin is missing".to_string())
+            }
+            (_, _, Some(origin)) if Some(origin) != own_origin.as_deref() => {
+                Some(format!("Origin {origin} can't manage permissions"))
+            }
+            _ => None,
+        };
+        request::Outcome::Success(Owner { denied })
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn check_owner(owner: &Owner) -> Result<(), RfidStatusResponse> {
+    match &owner.denied {
+        None => Ok(()),
+        Some(reason) => {
+            warn!("{reason}. Interrupting...");
+            Err(RfidStatusResponse::Err403(RfidResponse::default()))
+        }
+    }
+}
+
+#[derive(FromForm, Debug)]
+pub struct PermissionRequest<'r> {
+    origin: &'r str,
+    permission: Option<&'r str>,
+    // The origin is removed, so the user will be asked again
+    revoke: bool,
+}
+
+// GET http://127.0.0.1:21646/permissions
+#[get("/permissions")]
+pub fn handler(
+    permissions: &State<Permissions>,
+    config: &State<Config>,
+    owner: Owner,
+) -> RfidStatusResponse {
+    if let Err(r) = check_owner(&owner) {
+        return r;
+    }
+
+    let mut rows = String::new();
+    for (origin, permission) in permissions.list() {
+        let origin = escape_html(&origin);
+        let mut options = String::new();
+        for option in [Permission::Read, Permission::Write, Permission::Deny] {
+            let selected = if option == permission {
+                " selected"
+            } else {
+                ""
+            };
+            options += &format!("<option{selected}>{}</option>", option.as_str());
+        }
+        rows += &format!(
+            "<tr><td>{origin}</td><td><form method=\"post\">\
+             <input type=\"hidden\" name=\"origin\" value=\"{origin}\">\
+             <select name=\"permission\">{options}</select> \
+             <button>Save</button> \
+             <button name=\"revoke\" value=\"true\">Revoke</button>\
+             </form></td></tr>"
+        );
+    }
+    let note = if config.pair_origins() {
+        "The user is asked about new websites. A revoked website is asked about again."
+    } else {
+        "Websites aren't paired, because pair_origins is disabled in config.json."
+    };
+
+    let mut response = RfidResponse::from_string(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <title>RFID Server For Libraries</title></head><body>\
+         <h1>Paired websites</h1><p>{note}</p>\
+         <table><tr><th>Origin</th><th>Permission</th></tr>{rows}</table>\
+         </body></html>"
+    ));
+    response.make_html();
+    RfidStatusResponse::Ok(response)
+}
+
+// POST http://127.0.0.1:21646/permissions with `origin=<origin>&permission=<read|write|deny>`
+// or `origin=<origin>&revoke=true`
+#[allow(clippy::result_large_err)]
+#[post("/permissions", data = "<params>")]
+pub fn change_handler(
+    permissions: &State<Permissions>,
+    owner: Owner,
+    params: Form<PermissionRequest<'_>>,
+) -> Result<Redirect, RfidStatusResponse> {
+    check_owner(&owner)?;
+
+    if permissions.get(params.origin).is_none() {
+        return Err(RfidStatusResponse::Err404(RfidResponse::default()));
+    }
+    if params.revoke {
+        permissions.revoke(params.origin);
+    } else {
+        match params.permission.map(Permission::from_str) {
+            Some(Ok(p)) => permissions.set(params.origin, p),
+            _ => return Err(RfidStatusResponse::Err400(RfidResponse::default())),
+        }
+    }
+    Ok(Redirect::to("/permissions"))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,196 @@
+use super::{
+    check_access, check_local_access, confirm_by_user, device_failure, write_tags::WriteRequest,
+    Access, RfidResponse, RfidStatusResponse,
+};
+use crate::{
+    config::Config,
+    devices::{DevicesList, BLOCK_SIZE},
+    rfid_items::{from_hex_string, to_hex_string},
+};
+use log::*;
+use rocket::serde::{json, Deserialize, Serialize};
+use rocket::State;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+#[allow(non_snake_case)]
+pub struct BlocksResponse {
+    tagId: String,
+    firstBlock: u8,
+    data: String,
+}
+
+// GET http://127.0.0.1:21646/rfid/?action=readBlocks&deviceId=<deviceId>&tagId=<tagId>&firstBlock=<n>&count=<n>
+// Available only if `raw_blocks_access` is enabled in the config
+#[allow(non_snake_case)]
+#[get(
+    "/?action=readBlocks&<deviceId>&<tagId>&<firstBlock>&<count>",
+    rank = 3
+)]
+pub async fn read_handler(
+    shared_resource: &State<DevicesList>,
+    config: &State<Config>,
+    access: &Access,
+    deviceId: &str,
+    tagId: &str,
+    firstBlock: u8,
+    count: u8,
+) -> RfidStatusResponse {
+    // Check if the client is allowed. If not then exit
+    if let Err(r) = check_access(access).and(check_local_access(access)) {
+        return r;
+    }
+    if !config.raw_blocks_access() {
+        warn!("Raw blocks access is disabled in the config");
+        return RfidStatusResponse::Err403(RfidResponse::default());
+    }
+
+    let uid = match from_hex_string(tagId) {
+        Ok(uid) => uid,
+        Err(_) => return RfidStatusResponse::Err400(RfidResponse::default()),
+    };
+    let blocks = match firstBlock.checked_add(count) {
+        Some(end) if count > 0 => firstBlock..end,
+        _ => {
+            debug!("Blocks are out of range");
+            return RfidStatusResponse::Err400(RfidResponse::default());
+        }
+    };
+
+    let get_device = shared_resource.device(deviceId);
+    if get_device.is_none() {
+        debug!("Wrong device");
+        return RfidStatusResponse::Err404(RfidResponse::default());
+    }
+
+    let device = get_device.unwrap();
+    let read_uid = uid.clone();
+    let result = device
+        .run(move |device| {
+            device.connect();
+            device.read_blocks(&read_uid, blocks)
+        })
+        .await;
+
+    match result {
+        None => device_failure(deviceId),
+        Some(Ok(data)) => {
+            let response = json::to_string(&BlocksResponse {
+                tagId: to_hex_string(&uid),
+                firstBlock,
+                data: to_hex_string(&data),
+            })
+            .unwrap();
+            debug!("{response}");
+            RfidStatusResponse::Ok(RfidResponse::from_string(response))
+        }
+        Some(Err(e)) => {
+            info!("Unable to read blocks of tag {tagId}: {}", e.message);
+            RfidStatusResponse::Err404(RfidResponse::from_string(json::to_string(&e).unwrap()))
+        }
+    }
+}
+
+// OPTIONS http://127.0.0.1:21646/rfid/?action=readBlocks&deviceId=<deviceId>&tagId=<tagId>&firstBlock=<n>&count=<n>
+#[allow(non_snake_case)]
+#[options(
+    "/?action=readBlocks&<deviceId>&<tagId>&<firstBlock>&<count>",
+    rank = 3
+)]
+pub async fn read_handler_options(
+    shared_resource: &State<DevicesList>,
+    config: &State<Config>,
+    access: &Access,
+    deviceId: &str,
+    tagId: &str,
+    firstBlock: u8,
+    count: u8,
+) -> RfidStatusResponse {
+    read_handler(
+        shared_resource,
+        config,
+        access,
+        deviceId,
+        tagId,
+        firstBlock,
+        count,
+    )
+    .await
+}
+
+// POST http://127.0.0.1:21646/rfid/ with `action=writeBlocks&deviceId=<deviceId>&id=<tagId>&firstBlock=<n>&data=<hex>`
+// Called from write_tags::handler, because all POST actions share the same route
+pub async fn write_handler(
+    shared_resource: &DevicesList,
+    config: &Config,
+    access: &Access,
+    params: &WriteRequest<'_>,
+) -> RfidStatusResponse {
+    if let Err(r) = check_local_access(access) {
+        return r;
+    }
+    if !config.raw_blocks_access() {
+        warn!("Raw blocks access is disabled in the config");
+        return RfidStatusResponse::Err403(RfidResponse::default());
+    }
+
+    // Check if params are valid
+    let (tag_id, first_block, data) = match (params.id.first(), params.firstBlock, params.data) {
+        (Some(t), Some(f), Some(d)) => (t, f, d),
+        _ => {
+            debug!("Params are not valid!");
+            return RfidStatusResponse::Err400(RfidResponse::default());
+        }
+    };
+    let (uid, data) = match (from_hex_string(tag_id), from_hex_string(data)) {
+        (Ok(u), Ok(d))
+            if !d.is_empty()
+                && d.len() % BLOCK_SIZE == 0
+                && first_block as usize + d.len() / BLOCK_SIZE <= u8::MAX as usize + 1 =>
+        {
+            (u, d)
+        }
+        _ => {
+            debug!("Tag id or data is not valid!");
+            return RfidStatusResponse::Err400(RfidResponse::default());
+        }
+    };
+
+    let get_device = shared_resource.device(params.deviceId);
+    if get_device.is_none() {
+        return RfidStatusResponse::Err404(RfidResponse::default());
+    }
+
+    let device = get_device.unwrap();
+
+    if !confirm_by_user(config, "Записать блоки данных на карту?") {
+        return RfidStatusResponse::Err404(RfidResponse::default());
+    }
+
+    info!(
+        "Writing {} blocks to tag {tag_id} starting from block {first_block}",
+        data.len() / BLOCK_SIZE
+    );
+    let (write_uid, write_data) = (uid.clone(), data.clone());
+    let result = device
+        .run(move |device| {
+            device.connect();
+            device.write_blocks(&write_uid, first_block, &write_data)
+        })
+        .await;
+    match result {
+        None => device_failure(params.deviceId),
+        Some(Ok(())) => RfidStatusResponse::Ok(RfidResponse::from_string(
+            json::to_string(&BlocksResponse {
+                tagId: to_hex_string(&uid),
+                firstBlock: first_block,
+                data: to_hex_string(&data),
+            })
+            .unwrap(),
+        )),
+        Some(Err(e)) => {
+            warn!("Unable to write blocks of tag {tag_id}: {}", e.message);
+            RfidStatusResponse::Err404(RfidResponse::from_string(json::to_string(&e).unwrap()))
+        }
+    }
+}
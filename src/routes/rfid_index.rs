@@ -1,11 +1,11 @@
-use super::{check_if_addr_local, RfidResponse, RfidStatusResponse};
-use rocket_client_addr::ClientAddr;
+use super::{check_access, Access, RfidResponse, RfidStatusResponse};
 
 // GET http://127.0.0.1:21646/rfid/
-#[get("/")]
-pub fn handler(client_addr: &ClientAddr) -> RfidStatusResponse {
-    // Check if remote address is local. If not then exit
-    if let Err(r) = check_if_addr_local(client_addr) {
+// Matches any query, so it goes after the actions
+#[get("/", rank = 10)]
+pub fn handler(access: &Access) -> RfidStatusResponse {
+    // Check if the client is allowed. If not then exit
+    if let Err(r) = check_access(access) {
         return r;
     }
     let mut response = RfidResponse::from_str("./rfid works");
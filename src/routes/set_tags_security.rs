@@ -0,0 +1,60 @@
+use super::{
+    confirm_by_user, device_failure, not_supported, write_tags::WriteRequest, RfidResponse,
+    RfidStatusResponse,
+};
+use crate::{config::Config, devices::DevicesList};
+use log::*;
+use rocket::serde::json;
+use std::collections::HashMap;
+
+// POST http://127.0.0.1:21646/rfid/ with `action=setTagsSecurity`
+// Called from write_tags::handler, because both actions share the same route
+pub async fn handler(
+    shared_resource: &DevicesList,
+    config: &Config,
+    params: &WriteRequest<'_>,
+) -> RfidStatusResponse {
+    let get_device = shared_resource.device(params.deviceId);
+    if get_device.is_none() {
+        return RfidStatusResponse::Err404(RfidResponse::default());
+    }
+
+    let device = get_device.unwrap();
+    if !device.features().security_is_supported {
+        debug!("Device {} can't change security bits", params.deviceId);
+        return not_supported("Reader doesn't support changing security of tags");
+    }
+
+    debug!("Set tags security: {:?}", params);
+
+    // Check if params are valid
+    if params.id.is_empty() || params.id.len() != params.isSecured.len() {
+        debug!("Params are not valid!");
+        return RfidStatusResponse::Err400(RfidResponse::default());
+    }
+
+    let mut tags = HashMap::with_capacity(params.id.len());
+    for i in 0..params.id.len() {
+        tags.insert(params.id[i].to_string(), params.isSecured[i]);
+    }
+
+    if confirm_by_user(config, "Изменить защиту карт на считывателе?")
+    {
+        let responses = device
+            .run(move |device| {
+                device.connect();
+                device.set_tags_security(tags)
+            })
+            .await;
+        let responses = match responses {
+            Some(r) => r,
+            None => return device_failure(params.deviceId),
+        };
+        debug!("Set tags security responses: {:?}", responses);
+        RfidStatusResponse::Ok(RfidResponse::from_string(
+            json::to_string(&responses).unwrap(),
+        ))
+    } else {
+        RfidStatusResponse::Err404(RfidResponse::default())
+    }
+}
@@ -0,0 +1,466 @@
+use super::{
+    check_access, device_failure, not_supported,
+    write_tags::{WriteError, WriteRequest},
+    Access, RfidResponse, RfidStatusResponse,
+};
+use crate::{config::Config, devices::DevicesList};
+use log::*;
+use rocket::data::{IoHandler, IoStream};
+use rocket::form::Form;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::{json, json::Value, Deserialize, Serialize};
+use rocket::tokio::io::{self, AsyncRead, AsyncWrite};
+use rocket::tokio::{sync::mpsc, task, time};
+use rocket::State;
+use std::{pin::Pin, sync::Arc, time::Duration};
+use tokio_tungstenite::tungstenite::{
+    handshake::derive_accept_key,
+    protocol::{frame::coding::CloseCode, CloseFrame, Role, WebSocketConfig},
+    Error as WsError, Message,
+};
+use tokio_tungstenite::WebSocketStream;
+
+// Command sent by a client. Fields are the same as parameters of HTTP requests.
+// `requestId` is any JSON value which is sent back with the result
+#[derive(Deserialize, Debug, Default)]
+#[serde(crate = "rocket::serde", default)]
+#[allow(non_snake_case)]
+struct Command {
+    requestId: Value,
+    action: String,
+    deviceId: String,
+    tagId: String,
+    rawData: Option<bool>,
+    maxAge: Option<u64>,
+    id: Vec<String>,
+    itemId: Vec<String>,
+    r#type: Vec<u8>,
+    libraryId: Vec<String>,
+    itemSize: Vec<u8>,
+    indexInItemPack: Vec<u8>,
+    isSecured: Vec<bool>,
+    lockBlocks: Option<bool>,
+}
+
+// Result of a command. `status` is the http status of the same HTTP request
+// and `data` is its response or null if it's empty
+#[derive(Serialize, Debug)]
+#[serde(crate = "rocket::serde")]
+#[allow(non_snake_case)]
+struct CommandResult {
+    requestId: Value,
+    status: u16,
+    data: Value,
+}
+
+// Messages are small JSON commands, so anything bigger is an error
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+// The only version of the protocol, see RFC 6455
+const VERSION: &str = "13";
+// How long a client may take to answer a closing frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Opening handshake of a client. Requests without `Upgrade: websocket`,
+// `Connection: upgrade` or a key are forwarded to handler_not_upgraded()
+pub struct Handshake<'r> {
+    key: &'r str,
+    version: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Handshake<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let headers = request.headers();
+        // Both headers are lists of case-insensitive tokens
+        let has_token = |name: &str, token: &str| {
+            headers
+                .get(name)
+                .flat_map(|value| value.split(','))
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        };
+        let key = headers.get_one("Sec-WebSocket-Key");
+        match key {
+            Some(key)
+                if has_token("Upgrade", "websocket") && has_token("Connection", "upgrade") =>
+            {
+                request::Outcome::Success(Handshake {
+                    key,
+                    version: headers.get_one("Sec-WebSocket-Version"),
+                })
+            }
+            _ => request::Outcome::Forward(Status::BadRequest),
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum Upgrade {
+    Accepted(WebSocket),
+    // Clients are told which version is supported
+    #[response(status = 426)]
+    WrongVersion(RfidResponse, Header<'static>),
+}
+
+pub struct WebSocket {
+    accept: String,
+    connection: Connection,
+}
+
+impl<'r> Responder<'r, 'static> for WebSocket {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        // Rocket sets the status and the rest of the upgrade headers itself
+        Response::build()
+            .raw_header("Sec-WebSocket-Accept", self.accept)
+            .upgrade("websocket", self.connection)
+            .ok()
+    }
+}
+
+// Commands are run on their own tasks, so the connection outlives the request
+struct Connection {
+    devices_list: DevicesList,
+    config: Config,
+    access: Access,
+}
+
+// GET ws://127.0.0.1:21646/rfid/ws
+// Each text message is a JSON command, e.g. {"requestId": 1, "action": "getItemsList", "deviceId": "..."}
+#[get("/ws")]
+#[allow(clippy::result_large_err)]
+pub fn handler(
+    shared_resource: &State<DevicesList>,
+    config: &State<Config>,
+    access: &Access,
+    handshake: Handshake<'_>,
+) -> Result<Upgrade, RfidStatusResponse> {
+    // Check if the client is allowed. If not then exit
+    check_access(access)?;
+    if handshake.version != Some(VERSION) {
+        debug!("Unsupported WebSocket version {:?}", handshake.version);
+        return Ok(Upgrade::WrongVersion(
+            RfidResponse::from_str("{\"message\": \"WebSocket version 13 is expected\"}"),
+            Header::new("Sec-WebSocket-Version", VERSION),
+        ));
+    }
+    Ok(Upgrade::Accepted(WebSocket {
+        accept: derive_accept_key(handshake.key.as_bytes()),
+        connection: Connection {
+            devices_list: shared_resource.inner().clone(),
+            config: config.inner().clone(),
+            access: access.clone(),
+        },
+    }))
+}
+
+// The same address without the upgrade headers
+#[get("/ws", rank = 2)]
+pub fn handler_not_upgraded(access: &Access) -> RfidStatusResponse {
+    if let Err(r) = check_access(access) {
+        return r;
+    }
+    RfidStatusResponse::Err400(RfidResponse::from_str(
+        "{\"message\": \"WebSocket connection is expected\"}",
+    ))
+}
+
+#[rocket::async_trait]
+impl IoHandler for Connection {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        Arc::new(*Pin::into_inner(self)).serve(io).await
+    }
+}
+
+impl Connection {
+    // Every command is run as its own task, so pings and closing are handled
+    // while a device is busy. Results are sent as soon as they're ready,
+    // so they may come in another order than the commands.
+    // Pings and closing frames are answered by tungstenite itself
+    async fn serve<S>(self: Arc<Self>, io: S) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        info!("WebSocket client is connected");
+        let config = WebSocketConfig {
+            max_message_size: Some(MAX_MESSAGE_SIZE),
+            max_frame_size: Some(MAX_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        let stream = WebSocketStream::from_raw_socket(io, Role::Server, Some(config)).await;
+        let (mut writer, mut reader) = stream.split();
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Message>();
+        let writing = task::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                let is_close = message.is_close();
+                writer.send(message).await.map_err(io_error)?;
+                if is_close {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let close = |code: CloseCode| {
+            Message::Close(Some(CloseFrame {
+                code,
+                reason: "".into(),
+            }))
+        };
+
+        loop {
+            let code = match reader.next().await {
+                // The closing handshake is finished
+                None => {
+                    info!("WebSocket client is disconnected");
+                    writing.abort();
+                    return Ok(());
+                }
+                Some(Ok(Message::Text(text))) => {
+                    let (connection, sender) = (self.clone(), sender.clone());
+                    task::spawn(async move {
+                        let _ = sender.send(Message::Text(connection.execute(&text).await));
+                    });
+                    continue;
+                }
+                Some(Ok(Message::Binary(_))) => CloseCode::Unsupported,
+                Some(Ok(_)) => continue,
+                Some(Err(WsError::Capacity(_))) => CloseCode::Size,
+                Some(Err(WsError::Utf8)) => CloseCode::Invalid,
+                Some(Err(WsError::Protocol(e))) => {
+                    warn!("WebSocket protocol error: {e}");
+                    CloseCode::Protocol
+                }
+                Some(Err(e)) => {
+                    writing.abort();
+                    return Err(io_error(e));
+                }
+            };
+            warn!("WebSocket connection is closed with code {code}");
+            let _ = sender.send(close(code));
+            break;
+        }
+        // The client answers the closing frame, the rest of its messages are ignored
+        let closing = async { while let Some(Ok(_)) = reader.next().await {} };
+        let _ = time::timeout(CLOSE_TIMEOUT, closing).await;
+        match writing.await {
+            Ok(result) => result,
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    // Runs a command with the handler of the same HTTP request
+    async fn execute(&self, text: &str) -> String {
+        let command: Command = match json::from_str(text) {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("Invalid WebSocket command: {e}");
+                let error = WriteError {
+                    r#type: "Params Error".to_string(),
+                    message: e.to_string(),
+                };
+                let error = json::to_string(&error).unwrap();
+                return result(
+                    Value::Null,
+                    RfidStatusResponse::Err400(RfidResponse::from_string(error)),
+                );
+            }
+        };
+        debug!("WebSocket command: {:?}", command);
+
+        let devices_list = <&State<DevicesList>>::from(&self.devices_list);
+        let config = <&State<Config>>::from(&self.config);
+        let access = &self.access;
+        let response = match command.action.as_str() {
+            "getDevicesList" => super::get_devices_list::handler(devices_list, access),
+            "getItemsList" => {
+                super::get_items_list::handler(
+                    devices_list,
+                    access,
+                    &command.deviceId,
+                    command.rawData,
+                    command.maxAge,
+                )
+                .await
+            }
+            "getTagsSecurity" => {
+                super::get_tags_security::handler(devices_list, access, &command.deviceId).await
+            }
+            "getTagInfo" => {
+                super::get_tag_info::handler(
+                    devices_list,
+                    access,
+                    &command.deviceId,
+                    &command.tagId,
+                )
+                .await
+            }
+            "writeTags" | "setTagsSecurity" => {
+                let params = WriteRequest {
+                    action: &command.action,
+                    deviceId: &command.deviceId,
+                    id: command.id.iter().map(|s| s.as_str()).collect(),
+                    itemId: command.itemId.iter().map(|s| s.as_str()).collect(),
+                    r#type: command.r#type.clone(),
+                    libraryId: command.libraryId.iter().map(|s| s.as_str()).collect(),
+                    itemSize: command.itemSize.clone(),
+                    indexInItemPack: command.indexInItemPack.clone(),
+                    isSecured: command.isSecured.clone(),
+                    lockBlocks: command.lockBlocks,
+                    firstBlock: None,
+                    data: None,
+                };
+                super::write_tags::handler(devices_list, config, access, Form::from(params)).await
+            }
+            "beep" => self.beep(&command.deviceId).await,
+            action => {
+                debug!("Unknown WebSocket action {action}");
+                let error = WriteError {
+                    r#type: "Params Error".to_string(),
+                    message: format!("Unknown action {action}"),
+                };
+                RfidStatusResponse::Err404(RfidResponse::from_string(
+                    json::to_string(&error).unwrap(),
+                ))
+            }
+        };
+        result(command.requestId, response)
+    }
+
+    // Makes the reader beep, so the user can find it. There's no HTTP request for it
+    async fn beep(&self, device_id: &str) -> RfidStatusResponse {
+        if let Err(r) = check_access(&self.access) {
+            return r;
+        }
+        let device = match self.devices_list.device(device_id) {
+            Some(device) => device,
+            None => {
+                debug!("Wrong device");
+                return RfidStatusResponse::Err404(RfidResponse::default());
+            }
+        };
+        if !device.features().beep_is_supported {
+            debug!("Device {device_id} can't beep");
+            return not_supported("Reader doesn't support beeping");
+        }
+
+        let result = device
+            .run(|device| {
+                device.connect();
+                device.beep()
+            })
+            .await;
+        match result {
+            None => device_failure(device_id),
+            Some(Ok(())) => RfidStatusResponse::Ok(RfidResponse::default()),
+            Some(Err(e)) => {
+                info!("Device {device_id} is unable to beep: {}", e.message);
+                RfidStatusResponse::Err404(RfidResponse::from_string(json::to_string(&e).unwrap()))
+            }
+        }
+    }
+}
+
+fn result(request_id: Value, response: RfidStatusResponse) -> String {
+    let (status, body) = response.into_parts();
+    let result = CommandResult {
+        requestId: request_id,
+        status,
+        data: json::from_str(&body).unwrap_or(Value::Null),
+    };
+    json::to_string(&result).unwrap()
+}
+
+fn io_error(e: WsError) -> io::Error {
+    match e {
+        WsError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+#[rocket::async_test]
+async fn commands_dont_block_the_connection() {
+    use crate::devices::test_device::TestDevice;
+    use crate::permissions::Permissions;
+    use std::{net::Ipv4Addr, thread};
+
+    let config: Config =
+        json::from_str(r#"{"log_to_file": false, "devices": [{"driver": "simulator"}]}"#).unwrap();
+    let devices_list = DevicesList::new(&config);
+    let permissions = Permissions::load(std::env::temp_dir().join("rfid_sfl_unused.json"));
+    let access = Access::new(
+        &config,
+        &permissions,
+        Some(Ipv4Addr::LOCALHOST.into()),
+        None,
+        None,
+        false,
+    );
+    let connection = Arc::new(Connection {
+        devices_list: devices_list.clone(),
+        config,
+        access,
+    });
+    let (client, server) = io::duplex(4096);
+    let serving = task::spawn(connection.serve(server));
+    let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+    let text = |text: &str| Message::Text(text.to_string());
+    async fn result<S>(client: &mut WebSocketStream<S>) -> Value
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => json::from_str(&text).unwrap(),
+            message => panic!("Unexpected message {message:?}"),
+        }
+    }
+
+    // The device is busy with another request
+    let device = devices_list.get()["Simulator"].clone();
+    let busy = task::spawn(async move {
+        device
+            .run(|_| thread::sleep(Duration::from_millis(500)))
+            .await
+    });
+    rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let command = r#"{"requestId": 1, "action": "getItemsList", "deviceId": "Simulator"}"#;
+    client.send(text(command)).await.unwrap();
+    client.send(Message::Ping(b"ping".to_vec())).await.unwrap();
+    let pong = client.next().await.unwrap().unwrap();
+    assert_eq!(pong, Message::Pong(b"ping".to_vec()));
+    let result_1 = result(&mut client).await;
+    assert_eq!(result_1["requestId"], 1);
+    assert_eq!(result_1["status"], 200);
+    busy.await.unwrap();
+
+    let command = r#"{"requestId": 2, "action": "beep", "deviceId": "Simulator"}"#;
+    client.send(text(command)).await.unwrap();
+    assert_eq!(result(&mut client).await["status"], 200);
+
+    devices_list.add("Basic", Box::new(TestDevice { is_basic: true }));
+    let command = r#"{"requestId": 3, "action": "beep", "deviceId": "Basic"}"#;
+    client.send(text(command)).await.unwrap();
+    let result_3 = result(&mut client).await;
+    assert_eq!(result_3["status"], 501);
+    assert_eq!(result_3["data"]["type"], "Not Supported");
+
+    client
+        .send(text(r#"{"requestId": 4, "action": "dance"}"#))
+        .await
+        .unwrap();
+    let result_4 = result(&mut client).await;
+    assert_eq!(result_4["status"], 404);
+    assert_eq!(result_4["data"]["message"], "Unknown action dance");
+
+    // Binary messages aren't commands
+    client.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    match client.next().await.unwrap().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Unsupported),
+        message => panic!("Unexpected message {message:?}"),
+    }
+    assert!(client.next().await.is_none());
+    serving.await.unwrap().unwrap();
+}
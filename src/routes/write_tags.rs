@@ -1,27 +1,37 @@
-use crate::{config::Config, devices::DevicesList, rfid_items::DanishRfidItem};
+use crate::{
+    config::Config,
+    devices::{Device, DevicesList, RawTag, BLOCK_SIZE},
+    rfid_items::{danish::DanishRfidItem, from_hex_string, to_hex_string, TagDataModel},
+};
 use log::*;
 use rocket::form::Form;
 use rocket::serde::{json, Deserialize, Serialize};
 use rocket::State;
-use rocket_client_addr::ClientAddr;
 
-use super::{check_if_addr_local, RfidResponse, RfidStatusResponse};
+use super::{
+    check_access, check_write_access, confirm_by_user, device_failure, not_supported, Access,
+    RfidResponse, RfidStatusResponse,
+};
 
 #[derive(FromForm, Debug)]
 #[allow(non_snake_case)]
 pub struct WriteRequest<'r> {
-    action: &'r str,
-    deviceId: &'r str,
-    id: Vec<&'r str>,
-    itemId: Vec<&'r str>,
-    r#type: Vec<u8>,
-    libraryId: Vec<&'r str>,
-    itemSize: Vec<u8>,
-    indexInItemPack: Vec<u8>,
+    pub action: &'r str,
+    pub deviceId: &'r str,
+    pub id: Vec<&'r str>,
+    pub itemId: Vec<&'r str>,
+    pub r#type: Vec<u8>,
+    pub libraryId: Vec<&'r str>,
+    pub itemSize: Vec<u8>,
+    pub indexInItemPack: Vec<u8>,
     // isSecuritySupported: Vec<bool>,
     // expirationDate: Vec<&'r str>,
-    // isSecured: Vec<bool>,
+    pub isSecured: Vec<bool>,
     // circulationType: Vec<u8>,
+    pub lockBlocks: Option<bool>,
+    // writeBlocks action
+    pub firstBlock: Option<u8>,
+    pub data: Option<&'r str>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -30,6 +40,9 @@ pub struct WriteResponse {
     pub id: String,
     pub success: bool,
     pub error: Option<WriteError>,
+    // Whether the primary block is locked. Present only if it was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -40,33 +53,36 @@ pub struct WriteError {
 }
 
 // POST http://127.0.0.1:21646/rfid/
+// Handles both `action=writeTags` and `action=setTagsSecurity`
 #[post("/", data = "<params>")]
-pub fn handler(
+pub async fn handler(
     shared_resource: &State<DevicesList>,
     config: &State<Config>,
-    client_addr: &ClientAddr,
+    access: &Access,
     params: Form<WriteRequest<'_>>,
 ) -> RfidStatusResponse {
-    // Check if remote address is local. If it's not then exit
-    if let Err(r) = check_if_addr_local(client_addr) {
+    // Check if the client is allowed to write. If it's not then exit
+    if let Err(r) = check_write_access(access) {
         return r;
     }
 
-    if params.action != "writeTags" {
-        return RfidStatusResponse::Err404(RfidResponse::default());
+    match params.action {
+        "writeTags" => (),
+        "setTagsSecurity" => {
+            return super::set_tags_security::handler(shared_resource, config, &params).await
+        }
+        "writeBlocks" => {
+            return super::raw_blocks::write_handler(shared_resource, config, access, &params).await
+        }
+        _ => return RfidStatusResponse::Err404(RfidResponse::default()),
     }
 
-    let get_device = shared_resource
-        .inner()
-        .get()
-        .get(&params.deviceId.to_string());
+    let get_device = shared_resource.device(params.deviceId);
     if get_device.is_none() {
         return RfidStatusResponse::Err404(RfidResponse::default());
     }
 
-    let device_mutex = get_device.unwrap();
-    let mut device = device_mutex.lock().unwrap();
-    device.connect();
+    let device = get_device.unwrap();
 
     debug!("Write tag: {:?}", params);
 
@@ -78,51 +94,134 @@ pub fn handler(
         || params.itemId.len() != params.indexInItemPack.len()
     {
         debug!("Params are not valid!");
-        return RfidStatusResponse::Err400(RfidResponse::default());
+        return invalid_params("Numbers of values of item fields don't match".to_string());
     }
 
-    for lib in &params.libraryId {
-        if lib.len() < 3 {
-            debug!("Library id is not valid!");
-            return RfidStatusResponse::Err400(RfidResponse::default());
-        }
+    if params.lockBlocks == Some(true) && !device.features().lock_is_supported {
+        debug!("Device {} can't lock blocks", params.deviceId);
+        return not_supported("Reader doesn't support locking blocks");
     }
 
-    let mut items = Vec::with_capacity(params.itemId.len());
+    // Tags that are on the reader now. Their optional blocks have to be kept
+    let current_tags = device
+        .run(|device| {
+            device.connect();
+            device.get_tags()
+        })
+        .await;
+    let current_tags = match current_tags {
+        Some(t) => t,
+        None => return device_failure(params.deviceId),
+    };
+    let mut tags = Vec::with_capacity(params.itemId.len());
+    // Items whose tags aren't on the reader, by their positions in the request
+    let mut not_found = Vec::new();
 
     for i in 0..params.itemId.len() {
         let mut item = DanishRfidItem::default();
+        // Tag ids of blank tags aren't known to clients, so they can be left out
+        if !params.id[i].is_empty() && params.id[i] != "{}" {
+            if let Err(e) = item.set_card_id_string(params.id[i]) {
+                return invalid_params(format!("Item {i}, id: {e}"));
+            }
+        }
         item.set_number_of_parts(params.itemSize[i]);
         item.set_ordinal_number(params.indexInItemPack[i]);
-        if item.set_item_id(params.itemId[i]).is_err()
-            || item.set_usage_type(params.r#type[i]).is_err()
-            || item.set_country(&params.libraryId[i][0..2]).is_err()
-            || item.set_library_id(&params.libraryId[i][3..]).is_err()
-        {
+
+        // Library id consists of a country code and a library code, e.g. RU-293
+        let (country, library_id) =
+            match (params.libraryId[i].get(0..2), params.libraryId[i].get(3..)) {
+                (Some(c), Some(l)) => (c, l),
+                _ => {
+                    debug!("Library id is not valid!");
+                    return invalid_params(format!(
+                        "Item {i}, libraryId: Library id must look like RU-293"
+                    ));
+                }
+            };
+
+        let result = item
+            .set_item_id(params.itemId[i])
+            .map_err(|e| ("itemId", e))
+            .and(
+                item.set_usage_type(params.r#type[i])
+                    .map_err(|e| ("type", e)),
+            )
+            .and(item.set_country(country).map_err(|e| ("libraryId", e)))
+            .and(
+                item.set_library_id(library_id)
+                    .map_err(|e| ("libraryId", e)),
+            );
+        if let Err((param, e)) = result {
             debug!("Params of an item are not valid!");
-            return RfidStatusResponse::Err400(RfidResponse::default());
+            return invalid_params(format!("Item {i}, {param}: {e}"));
+        }
+        // A tag without id is the only one on the reader. A tag with id is never replaced
+        // by another one, even if it's the only one
+        let current = match item.card_id().is_empty() {
+            true => current_tags.first().filter(|_| current_tags.len() == 1),
+            false => current_tags.iter().find(|t| t.uid == item.card_id()),
+        };
+        match current {
+            Some(current) => {
+                if let Ok(current_item) = DanishRfidItem::from_bytes(&current.memory) {
+                    item.set_optional_blocks(current_item.optional_blocks().to_vec());
+                }
+                item.set_card_id(current.uid.clone());
+            }
+            None if !item.card_id().is_empty() => {
+                debug!("Tag {} is not found", params.id[i]);
+                not_found.push((
+                    i,
+                    WriteResponse {
+                        id: to_hex_string(item.card_id()),
+                        success: false,
+                        error: Some(WriteError {
+                            r#type: "Write Error".to_string(),
+                            message: "Tag not found".to_string(),
+                        }),
+                        locked: None,
+                    },
+                ));
+                continue;
+            }
+            None => (),
         }
         debug!("item = {:?}", item);
-        items.push(item);
-    }
-
-    let mut confirm = true;
-
-    // Shows confirm dialog to a user
-    if config.ask_when_writing() {
-        confirm = rocket::tokio::task::block_in_place(|| {
-            native_dialog::MessageDialog::new()
-                .set_title("RFID Server For Libraries")
-                .set_text("Записать карту на считывателе?")
-                .set_type(native_dialog::MessageType::Info)
-                .show_confirm()
-                .unwrap_or(true)
+        tags.push(RawTag {
+            uid: item.card_id().to_vec(),
+            dsfid: None,
+            memory: item.to_bytes(),
         });
-        info!("User confirmation for writing a card: {confirm}");
     }
 
-    if confirm {
-        let responses = device.write_tags(items);
+    if confirm_by_user(config, "Записать карту на считывателе?") {
+        let verify_after_writing = config.verify_after_writing();
+        let lock_blocks = params.lockBlocks == Some(true);
+        let responses = device
+            .run(move |device| {
+                let mut responses = device.write_tags(tags.clone());
+                assert_eq!(
+                    responses.len(),
+                    tags.len(),
+                    "Driver has returned a wrong number of responses"
+                );
+                if verify_after_writing {
+                    verify(device, &tags, &mut responses);
+                }
+                if lock_blocks {
+                    lock(device, &mut responses);
+                }
+                for (i, response) in not_found {
+                    responses.insert(i, response);
+                }
+                responses
+            })
+            .await;
+        let responses = match responses {
+            Some(r) => r,
+            None => return device_failure(params.deviceId),
+        };
         debug!("Write tag responses: {:?}", responses);
         info!("Card(s) has been successfully written");
         RfidStatusResponse::Ok(RfidResponse::from_string(
@@ -133,12 +232,118 @@ pub fn handler(
     }
 }
 
+// The tag which a response is about. A tag without UID is the only one written
+fn written_tag<'a>(tags: &'a [RawTag], response: &WriteResponse) -> Option<&'a RawTag> {
+    let uid = from_hex_string(&response.id).unwrap_or_default();
+    tags.iter().find(|tag| tag.uid == uid).or(tags
+        .first()
+        .filter(|tag| tags.len() == 1 && tag.uid.is_empty()))
+}
+
+// Reads the written blocks back and checks that they contain the sent data.
+// Tag memory is read directly, so it isn't limited by the `read_blocks` option
+fn verify(device: &mut dyn Device, tags: &[RawTag], responses: &mut [WriteResponse]) {
+    for response in responses.iter_mut() {
+        if !response.success {
+            continue;
+        }
+        let Some(tag) = written_tag(tags, response) else {
+            warn!("Tag {} hasn't been requested to be written", response.id);
+            response.success = false;
+            response.error = Some(WriteError {
+                r#type: "Verification Error".to_string(),
+                message: "Tag hasn't been requested to be written".to_string(),
+            });
+            continue;
+        };
+        let memory = &tag.memory;
+        let uid = from_hex_string(&response.id).unwrap_or_default();
+        let blocks = 0..memory.len().div_ceil(BLOCK_SIZE) as u8;
+        let message = match device.read_blocks(&uid, blocks) {
+            Ok(data) if data.get(..memory.len()) == Some(&memory[..]) => continue,
+            Ok(_) => "Written data doesn't match the sent one".to_string(),
+            Err(e) => format!("Written blocks can't be read: {}", e.message),
+        };
+        warn!("Verification of tag {} failed: {message}", response.id);
+        response.success = false;
+        response.error = Some(WriteError {
+            r#type: "Verification Error".to_string(),
+            message,
+        });
+    }
+}
+
+// Locks primary blocks of successfully written tags. The data stays written
+// if locking fails, but the tag isn't written as requested, so it's a failure
+fn lock(device: &mut dyn Device, responses: &mut [WriteResponse]) {
+    for response in responses.iter_mut() {
+        if !response.success {
+            continue;
+        }
+        // Responses have UIDs of written tags, even of those which were requested without them
+        let uid = from_hex_string(&response.id).unwrap_or_default();
+        let blocks = DanishRfidItem::primary_blocks(BLOCK_SIZE);
+        match device.lock_blocks(&uid, blocks) {
+            Ok(()) => response.locked = Some(true),
+            Err(e) => {
+                warn!("Unable to lock tag {}: {}", response.id, e.message);
+                response.success = false;
+                response.locked = Some(false);
+                response.error = Some(e);
+            }
+        }
+    }
+}
+
+// Response with a description of invalid params
+fn invalid_params(message: String) -> RfidStatusResponse {
+    let error = WriteError {
+        r#type: "Params Error".to_string(),
+        message,
+    };
+    RfidStatusResponse::Err400(RfidResponse::from_string(json::to_string(&error).unwrap()))
+}
+
 // OPTIONS http://127.0.0.1:21646/rfid/
-#[options("/")]
-pub fn handler_options(client_addr: &ClientAddr) -> RfidStatusResponse {
-    // Check if remote address is local. If it's not then exit
-    if let Err(r) = check_if_addr_local(client_addr) {
+// Matches any query, so it goes after the actions
+#[options("/", rank = 10)]
+pub fn handler_options(access: &Access) -> RfidStatusResponse {
+    // Check if the client is allowed. If it's not then exit
+    if let Err(r) = check_access(access) {
         return r;
     }
     RfidStatusResponse::Ok(RfidResponse::default())
 }
+
+#[test]
+fn responses_are_matched_by_tag_ids() {
+    let tag = |uid: u8, memory: u8| RawTag {
+        uid: vec![0xE0, 0x04, 0x01, 0x50, 0, 0, 0, uid],
+        dsfid: None,
+        memory: vec![memory; 8],
+    };
+    let success = |tag: &RawTag| WriteResponse {
+        id: to_hex_string(&tag.uid),
+        success: true,
+        error: None,
+        locked: None,
+    };
+    // The test device reads zeros, so only the first tag is written as requested
+    let tags = [tag(1, 0x00), tag(2, 0xFF)];
+    let mut responses = [success(&tags[1]), success(&tags[0])];
+    let mut device = crate::devices::test_device::TestDevice::default();
+    verify(&mut device, &tags, &mut responses);
+    assert!(!responses[0].success);
+    assert!(responses[1].success);
+
+    // A tag without UID is the only one, its response has the real UID
+    let blank = RawTag {
+        uid: vec![],
+        ..tag(0, 0x00)
+    };
+    let mut responses = [success(&tags[1])];
+    verify(&mut device, &[blank], &mut responses);
+    assert!(responses[0].success);
+    lock(&mut device, &mut responses);
+    assert_eq!(responses[0].locked, Some(true));
+}